@@ -2,7 +2,8 @@ use srray::camera::Camera;
 use srray::color::Color;
 use srray::fractals::SDFMengerSponge;
 use srray::geometries::Plane;
-use srray::light::PointLight;
+use srray::integrator::Whitted;
+use srray::light::{Falloff, PointLight};
 use srray::materials::{None, PhongMaterial};
 use srray::objects::Object;
 use srray::point::Point;
@@ -39,11 +40,12 @@ fn main() {
                     tolerance: 1e-5,
                 }),
                 material: Box::new(PhongMaterial {
-                    ambient_color: Color::new(0.02, 0.02, 0.02),
-                    diffuse_color: Color::new(0.5, 0.5, 0.5),
-                    specular_color: Color::new(2., 2., 2.),
-                    specular_power: 50.,
+                    ambient_color: Box::new(Color::new(0.02, 0.02, 0.02)),
+                    diffuse_color: Box::new(Color::new(0.5, 0.5, 0.5)),
+                    specular_color: Box::new(Color::new(2., 2., 2.)),
+                    specular_power: Box::new(50.),
                 }),
+                medium: Option::None,
             },
             Object {
                 geometry: Box::new(Plane {
@@ -51,24 +53,35 @@ fn main() {
                     normal: Vector::new(0., 1., 1.),
                 }),
                 material: Box::new(PhongMaterial {
-                    ambient_color: Color::BLACK,
-                    diffuse_color: Color::new(0.1, 0.1, 0.1),
-                    specular_color: Color::new(1., 1., 1.),
-                    specular_power: 1.,
+                    ambient_color: Box::new(Color::BLACK),
+                    diffuse_color: Box::new(Color::new(0.1, 0.1, 0.1)),
+                    specular_color: Box::new(Color::new(1., 1., 1.)),
+                    specular_power: Box::new(1.),
                 }),
+                medium: Option::None,
             },
         ],
         lights: vec![
             Box::new(PointLight {
                 center: Point::new(-2., 1., 2.),
                 color: Color::new(4., 2., 2.),
+                profile: Option::None,
+                falloff: Falloff::InverseSquare,
+                radius: 0.,
+                spectrum: Option::None,
             }),
             Box::new(PointLight {
                 center: Point::new(2., 1., 2.),
                 color: Color::new(2., 2., 5.),
+                profile: Option::None,
+                falloff: Falloff::InverseSquare,
+                radius: 0.,
+                spectrum: Option::None,
             }),
         ],
         background: Box::new(None {}),
+        atmosphere: Option::None,
+        integrator: Box::new(Whitted {}),
     };
 
     let mut file = File::create("test.ppm").unwrap();
@@ -0,0 +1,395 @@
+use std::io::{Result, Write};
+
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::float::Float;
+
+// Arbitrary output variables, rendered alongside the beauty pass
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AOV {
+    Beauty,
+    // Distance along the camera axis, infinite for background pixels
+    Depth,
+    // World space surface normal, components on [-1, 1]
+    Normal,
+    Albedo,
+    // Index into scene.objects, -1 for background pixels
+    ObjectId,
+    // Index of the material in order of first appearance in scene.objects,
+    // see Material::id
+    MaterialId,
+    // World space hit position
+    Position,
+    // Direct contribution of the i-th of scene.light_sources()
+    Light(usize),
+}
+
+impl AOV {
+    // Layer in multi-layer files, the beauty pass is the default one
+    pub fn name(self) -> String {
+        match self {
+            AOV::Beauty => String::new(),
+            AOV::Depth => "depth".to_string(),
+            AOV::Normal => "normal".to_string(),
+            AOV::Albedo => "albedo".to_string(),
+            AOV::ObjectId => "object_id".to_string(),
+            AOV::MaterialId => "material_id".to_string(),
+            AOV::Position => "position".to_string(),
+            AOV::Light(i) => format!("light{}", i),
+        }
+    }
+
+    // Geometric data which should not be averaged over the pixel
+    pub fn is_filterable(self) -> bool {
+        !matches!(
+            self,
+            AOV::Depth | AOV::ObjectId | AOV::MaterialId | AOV::Position
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AOVBuffer {
+    width: u32,
+    height: u32,
+    aovs: Vec<AOV>,
+    // Layers are interleaved per pixel, pixels are laid out like in Buffer
+    data: Vec<Color>,
+}
+
+impl AOVBuffer {
+    pub fn new(width: u32, height: u32, aovs: &[AOV]) -> Self {
+        AOVBuffer {
+            width,
+            height,
+            aovs: aovs.to_vec(),
+            data: vec![Color::BLACK; (width * height) as usize * aovs.len()],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn aovs(&self) -> &[AOV] {
+        &self.aovs
+    }
+
+    pub fn layer(&self, aov: AOV) -> Option<usize> {
+        self.aovs.iter().position(|&a| a == aov)
+    }
+
+    pub fn get(&self, aov: AOV, x: u32, y: u32) -> Option<Color> {
+        let layer = self.layer(aov)?;
+        let idx = Buffer::cart_to_lin(self.height, x, y) as usize;
+        Some(self.data[idx * self.aovs.len() + layer])
+    }
+
+    pub fn enum_iter_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut [Color])> + Send {
+        let height = self.height;
+        self.data
+            .chunks_mut(self.aovs.len().max(1))
+            .enumerate()
+            .map(move |(idx, pixel)| {
+                let (x, y) = Buffer::lin_to_cart(height, idx as u32);
+                (x, y, pixel)
+            })
+    }
+
+    // Maps a layer to displayable colors
+    pub fn to_buffer(&self, aov: AOV) -> Option<Buffer> {
+        let layer = self.layer(aov)?;
+        let stride = self.aovs.len();
+        let pixels = self.data.iter().skip(layer).step_by(stride);
+
+        let max_depth = pixels
+            .clone()
+            .map(|c| c.red)
+            .filter(|d| d.is_finite())
+            .fold(0., Float::max);
+
+        let mut buffer = Buffer::new(self.width, self.height);
+        for (idx, &color) in pixels.enumerate() {
+            let display = match aov {
+                AOV::Depth if color.red.is_finite() && max_depth > 0. => {
+                    let gray = 1. - color.red / max_depth;
+                    Color::new(gray, gray, gray)
+                }
+                AOV::Depth => Color::BLACK,
+                AOV::Normal => Color::new(
+                    (color.red + 1.) / 2.,
+                    (color.green + 1.) / 2.,
+                    (color.blue + 1.) / 2.,
+                ),
+                AOV::ObjectId | AOV::MaterialId => id_color(color.red),
+                _ => color,
+            };
+            *buffer.get_lin_mut(idx as u32) = display.into();
+        }
+        Some(buffer)
+    }
+
+    // Portable float map, keeps the full range of the layer
+    pub fn write_pfm<W: Write>(&self, aov: AOV, out: &mut W) -> Result<()> {
+        let layer = match self.layer(aov) {
+            Some(layer) => layer,
+            None => return Ok(()),
+        };
+
+        // Write header, negative scale means little endian
+        writeln!(out, "PF")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "-1.0")?;
+
+        // Rows are stored bottom to top
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let idx = Buffer::cart_to_lin(self.height, x, y) as usize;
+                let color = self.data[idx * self.aovs.len() + layer];
+                for c in [color.red, color.green, color.blue] {
+                    #[allow(clippy::unnecessary_cast)]
+                    out.write_all(&(c as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    // Uncompressed scanline OpenEXR with all the layers, as float R, G and B
+    // channels named after AOV::name
+    pub fn write_exr<W: Write>(&self, out: &mut W) -> Result<()> {
+        // Channels are stored in alphabetical order
+        let mut channels: Vec<(String, usize, usize)> = Vec::new();
+        for (layer, aov) in self.aovs.iter().enumerate() {
+            let prefix = match aov.name() {
+                name if name.is_empty() => name,
+                name => name + ".",
+            };
+            for (c, suffix) in ["R", "G", "B"].iter().enumerate() {
+                channels.push((format!("{}{}", prefix, suffix), layer, c));
+            }
+        }
+        channels.sort();
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+        header.extend_from_slice(&2u32.to_le_bytes());
+        let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+            for s in [name, kind] {
+                header.extend_from_slice(s.as_bytes());
+                header.push(0);
+            }
+            header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            header.extend_from_slice(value);
+        };
+
+        let mut list = Vec::new();
+        for (name, _, _) in &channels {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+            // Float pixels, not perceptually linear, no subsampling
+            for value in [2i32, 0, 1, 1] {
+                list.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        list.push(0);
+        attribute("channels", "chlist", &list);
+        attribute("compression", "compression", &[0]);
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        attribute("dataWindow", "box2i", &window);
+        attribute("displayWindow", "box2i", &window);
+        attribute("lineOrder", "lineOrder", &[0]);
+        attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute("screenWindowCenter", "v2f", &[0; 8]);
+        attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+        out.write_all(&header)?;
+
+        // Offsets of the scanlines, which follow the table
+        let line_size = 8 + 4 * channels.len() as u64 * self.width as u64;
+        let start = header.len() as u64 + 8 * self.height as u64;
+        for y in 0..self.height as u64 {
+            out.write_all(&(start + y * line_size).to_le_bytes())?;
+        }
+
+        // Scanlines from the top, each channel in turn
+        for y in 0..self.height {
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&((line_size - 8) as i32).to_le_bytes())?;
+            for (_, layer, c) in &channels {
+                for x in 0..self.width {
+                    let idx = Buffer::cart_to_lin(self.height, x, y) as usize;
+                    let color = self.data[idx * self.aovs.len() + layer];
+                    let value = [color.red, color.green, color.blue][*c];
+                    #[allow(clippy::unnecessary_cast)]
+                    out.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+// Spread consecutive ids over the hue circle
+fn id_color(id: Float) -> Color {
+    if id < 0. {
+        return Color::BLACK;
+    }
+
+    let hue = (id * 0.618_033_988_749_895).fract() * 6.;
+    let x = 1. - (hue % 2. - 1.).abs();
+    match hue as u32 {
+        0 => Color::new(1., x, 0.),
+        1 => Color::new(x, 1., 0.),
+        2 => Color::new(0., 1., x),
+        3 => Color::new(0., x, 1.),
+        4 => Color::new(x, 0., 1.),
+        _ => Color::new(1., 0., x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    // Layer values encode the layer and the pixel
+    fn buffer(width: u32, height: u32, aovs: &[AOV]) -> AOVBuffer {
+        let mut buffer = AOVBuffer::new(width, height, aovs);
+        for (x, y, pixel) in buffer.enum_iter_mut() {
+            for (layer, value) in pixel.iter_mut().enumerate() {
+                let base = (100 * layer as u32 + 10 * y + x) as Float;
+                *value = Color::new(base, base + 0.25, base + 0.5);
+            }
+        }
+        buffer
+    }
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_f32(bytes: &[u8], at: usize) -> f32 {
+        f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_string(bytes: &[u8], at: usize) -> String {
+        let end = at + bytes[at..].iter().position(|&b| b == 0).unwrap();
+        String::from_utf8(bytes[at..end].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn pfm_rows_bottom_to_top() {
+        let buffer = buffer(2, 3, &[AOV::Beauty, AOV::Depth]);
+        let mut bytes = Vec::new();
+        buffer.write_pfm(AOV::Depth, &mut bytes).unwrap();
+
+        let header = "PF\n2 3\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header.as_bytes());
+        assert_eq!(bytes.len(), header.len() + 4 * 3 * 2 * 3);
+        let pixel = |i: usize| read_f32(&bytes, header.len() + 12 * i);
+        // Bottom left, then bottom right, ending at the top right
+        assert_eq!(pixel(0), 120.);
+        assert_eq!(pixel(1), 121.);
+        assert_eq!(pixel(5), 101.);
+        assert_eq!(read_f32(&bytes, header.len() + 4), 120.25);
+
+        let mut nothing = Vec::new();
+        buffer.write_pfm(AOV::Normal, &mut nothing).unwrap();
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn exr_layout() {
+        let (width, height) = (3, 2);
+        let buffer = buffer(width, height, &[AOV::ObjectId, AOV::Beauty, AOV::Depth]);
+        let mut bytes = Vec::new();
+        buffer.write_exr(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(read_u32(&bytes, 4), 2);
+
+        // Attributes up to the empty name ending the header
+        let mut at = 8;
+        let mut attributes = Vec::new();
+        loop {
+            let name = read_string(&bytes, at);
+            at += name.len() + 1;
+            if name.is_empty() {
+                break;
+            }
+            let kind = read_string(&bytes, at);
+            at += kind.len() + 1;
+            let size = read_u32(&bytes, at) as usize;
+            attributes.push((name, kind, at + 4));
+            at += 4 + size;
+        }
+        let header_size = at;
+        let names: Vec<_> = attributes.iter().map(|a| a.0.as_str()).collect();
+        for required in [
+            "channels",
+            "compression",
+            "dataWindow",
+            "displayWindow",
+            "lineOrder",
+            "pixelAspectRatio",
+            "screenWindowCenter",
+            "screenWindowWidth",
+        ] {
+            assert!(names.contains(&required), "missing {}", required);
+        }
+
+        // Channels sorted by name, all float
+        let (_, kind, mut at) = attributes[0].clone();
+        assert_eq!(kind, "chlist");
+        let mut channels = Vec::new();
+        loop {
+            let name = read_string(&bytes, at);
+            at += name.len() + 1;
+            if name.is_empty() {
+                break;
+            }
+            assert_eq!(read_u32(&bytes, at), 2);
+            at += 16;
+            channels.push(name);
+        }
+        let expected = [
+            "B",
+            "G",
+            "R",
+            "depth.B",
+            "depth.G",
+            "depth.R",
+            "object_id.B",
+            "object_id.G",
+            "object_id.R",
+        ];
+        assert_eq!(channels, expected);
+
+        // Offsets point to consecutive scanlines of line_size bytes
+        let line_size = 8 + 4 * channels.len() * width as usize;
+        let start = header_size + 8 * height as usize;
+        for y in 0..height as usize {
+            let offset = u64::from_le_bytes(
+                bytes[header_size + 8 * y..header_size + 8 * y + 8]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            assert_eq!(offset, start + y * line_size);
+            assert_eq!(read_u32(&bytes, offset), y as u32);
+            assert_eq!(read_u32(&bytes, offset + 4) as usize, line_size - 8);
+        }
+        assert_eq!(bytes.len(), start + height as usize * line_size);
+
+        // Second scanline, channel "depth.G" of the layer at index 2
+        let channel = 4;
+        let value = |x: usize| read_f32(&bytes, start + line_size + 8 + 4 * (channel * 3 + x));
+        assert_eq!(value(0), 210.25);
+        assert_eq!(value(2), 212.25);
+    }
+}
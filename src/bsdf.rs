@@ -0,0 +1,372 @@
+use crate::color::Color;
+use crate::float::consts::FRAC_1_PI;
+use crate::float::Float;
+use crate::sampling::{cosine_hemisphere, random};
+use crate::vector::Vector;
+
+// Directions point away from the surface: wo towards the viewer, wi towards the light
+pub trait BSDF {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color;
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample>;
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BSDFSample {
+    pub direction: Vector,
+    pub value: Color,
+    pub pdf: Float,
+    // Delta distributions can't be evaluated, value and pdf are relative to each other
+    pub specular: bool,
+}
+
+impl BSDFSample {
+    // Throughput factor of the sampled path segment
+    pub fn weight(&self, normal: Vector) -> Color {
+        self.value * ((self.direction * normal).abs() / self.pdf)
+    }
+}
+
+// Orthonormal shading frame with n as local z-axis
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub s: Vector,
+    pub t: Vector,
+    pub n: Vector,
+}
+
+impl Frame {
+    pub fn from_normal(normal: Vector) -> Frame {
+        let n = normal.normalize();
+        let (s, t) = n.orthonormals();
+        Frame { s, t, n }
+    }
+
+    // s along the tangent projected onto the surface, which orients anisotropic
+    // BSDFs. Any frame if the tangent is degenerate.
+    pub fn from_tangent(normal: Vector, tangent: Vector) -> Frame {
+        let n = normal.normalize();
+        let projected = tangent - (tangent * n) * n;
+        let length = projected.norm_squared();
+        if !length.is_finite() || length <= 1e-12 * tangent.norm_squared() {
+            return Frame::from_normal(n);
+        }
+        let s = projected.normalize();
+        let t = n.cross(s);
+        Frame { s, t, n }
+    }
+
+    pub fn to_local(&self, v: Vector) -> Vector {
+        Vector::new(v * self.s, v * self.t, v * self.n)
+    }
+
+    pub fn to_world(&self, v: Vector) -> Vector {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+pub fn reflect(w: Vector, normal: Vector) -> Vector {
+    2. * (w * normal) * normal - w
+}
+
+// Transmitted direction for a relative index of refraction eta = eta_t / eta_i,
+// w and normal on the same side. None on total internal reflection.
+pub fn refract(w: Vector, normal: Vector, eta: Float) -> Option<Vector> {
+    let cos_i = w * normal;
+    let sin2_t = (1. - cos_i * cos_i).max(0.) / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-w * eta.recip() + (cos_i / eta - cos_t) * normal)
+}
+
+// Unpolarized reflectance, cos_i with respect to the normal on the outside
+pub fn fresnel_dielectric(cos_i: Float, eta: Float) -> Float {
+    if cos_i < 0. {
+        return fresnel_dielectric(-cos_i, eta.recip());
+    }
+
+    let cos_i = cos_i.min(1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.
+}
+
+// Unpolarized reflectance of a conductor with complex index of refraction eta + ik
+pub fn fresnel_conductor(cos_i: Float, eta: Color, k: Color) -> Color {
+    let cos = cos_i.clamp(0., 1.);
+    let channel = |eta: Float, k: Float| {
+        let cos2 = cos * cos;
+        let sin2 = 1. - cos2;
+
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = ((a2b2 + t0) / 2.).max(0.).sqrt();
+        let t2 = 2. * cos * a;
+        let r_perp = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_parl = r_perp * (t3 - t4) / (t3 + t4);
+
+        (r_parl + r_perp) / 2.
+    };
+
+    Color::new(
+        channel(eta.red, k.red),
+        channel(eta.green, k.green),
+        channel(eta.blue, k.blue),
+    )
+}
+
+// Two-sided
+pub struct LambertianBSDF {
+    pub frame: Frame,
+    pub albedo: Color,
+}
+
+impl BSDF for LambertianBSDF {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color {
+        if (wo * self.frame.n) * (wi * self.frame.n) > 0. {
+            FRAC_1_PI * self.albedo
+        } else {
+            Color::BLACK
+        }
+    }
+
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample> {
+        let mut local = cosine_hemisphere(u);
+        if wo * self.frame.n < 0. {
+            local.z = -local.z;
+        }
+        if local.z == 0. {
+            return None;
+        }
+
+        Some(BSDFSample {
+            direction: self.frame.to_world(local),
+            value: FRAC_1_PI * self.albedo,
+            pdf: local.z.abs() * FRAC_1_PI,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float {
+        if (wo * self.frame.n) * (wi * self.frame.n) > 0. {
+            (wi * self.frame.n).abs() * FRAC_1_PI
+        } else {
+            0.
+        }
+    }
+}
+
+pub struct SpecularReflection {
+    pub normal: Vector,
+    pub reflectance: Color,
+}
+
+impl BSDF for SpecularReflection {
+    fn evaluate(&self, _wo: Vector, _wi: Vector) -> Color {
+        Color::BLACK
+    }
+
+    fn sample(&self, wo: Vector, _u: (Float, Float)) -> Option<BSDFSample> {
+        let direction = reflect(wo, self.normal);
+        let cos = (direction * self.normal).abs();
+        if cos == 0. {
+            return None;
+        }
+
+        Some(BSDFSample {
+            direction,
+            value: self.reflectance * cos.recip(),
+            pdf: 1.,
+            specular: true,
+        })
+    }
+
+    fn pdf(&self, _wo: Vector, _wi: Vector) -> Float {
+        0.
+    }
+}
+
+// Smooth interface, the normal points to the outside of the medium
+pub struct SpecularDielectric {
+    pub normal: Vector,
+    pub ior: Float,
+}
+
+impl BSDF for SpecularDielectric {
+    fn evaluate(&self, _wo: Vector, _wi: Vector) -> Color {
+        Color::BLACK
+    }
+
+    fn sample(&self, wo: Vector, _u: (Float, Float)) -> Option<BSDFSample> {
+        let (normal, eta) = if wo * self.normal >= 0. {
+            (self.normal, self.ior)
+        } else {
+            (-self.normal, self.ior.recip())
+        };
+
+        let reflectance = fresnel_dielectric(wo * normal, eta);
+        let (direction, fraction, pdf, transmitted) = match refract(wo, normal, eta) {
+            Some(direction) if random() >= reflectance => (
+                direction,
+                (1. - reflectance) / (eta * eta),
+                1. - reflectance,
+                true,
+            ),
+            _ => (reflect(wo, normal), reflectance, reflectance, false),
+        };
+
+        let cos = direction * normal;
+        if cos == 0. || (cos < 0.) != transmitted {
+            return None;
+        }
+
+        Some(BSDFSample {
+            direction,
+            value: Color::new(1., 1., 1.) * (fraction / cos.abs()),
+            pdf,
+            specular: true,
+        })
+    }
+
+    fn pdf(&self, _wo: Vector, _wi: Vector) -> Float {
+        0.
+    }
+}
+
+// Weighted sum of BSDFs, the weights can depend on the outgoing direction wo
+// and are used as the probabilities to sample each component
+pub struct MixBSDF<'a> {
+    pub bsdfs: Vec<Box<dyn BSDF + 'a>>,
+    pub weights: Box<dyn Fn(Vector) -> Vec<Float> + 'a>,
+}
+
+impl BSDF for MixBSDF<'_> {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color {
+        let mut value = Color::BLACK;
+        for (bsdf, weight) in self.bsdfs.iter().zip((self.weights)(wo)) {
+            if weight > 0. {
+                value += weight * bsdf.evaluate(wo, wi);
+            }
+        }
+        value
+    }
+
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample> {
+        let weights = (self.weights)(wo);
+        let total: Float = weights.iter().sum();
+        if total <= 0. {
+            return None;
+        }
+
+        // Pick a component and reuse the remainder of u.0
+        let mut u0 = u.0 * total;
+        let mut chosen = weights.len() - 1;
+        for (i, weight) in weights.iter().enumerate() {
+            if u0 < *weight {
+                chosen = i;
+                break;
+            }
+            u0 -= weight;
+        }
+        let weight = weights[chosen];
+        if weight <= 0. {
+            return None;
+        }
+        let u = ((u0 / weight).clamp(0., 1. - Float::EPSILON), u.1);
+
+        let sample = self.bsdfs[chosen].sample(wo, u)?;
+        if sample.specular {
+            return Some(BSDFSample {
+                value: weight * sample.value,
+                pdf: weight / total * sample.pdf,
+                ..sample
+            });
+        }
+
+        Some(BSDFSample {
+            value: self.evaluate(wo, sample.direction),
+            pdf: self.pdf(wo, sample.direction),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float {
+        let weights = (self.weights)(wo);
+        let total: Float = weights.iter().sum();
+        if total <= 0. {
+            return 0.;
+        }
+
+        let mut pdf = 0.;
+        for (bsdf, weight) in self.bsdfs.iter().zip(weights) {
+            if weight > 0. {
+                pdf += weight / total * bsdf.pdf(wo, wi);
+            }
+        }
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_orthonormal() {
+        for normal in [Vector::K, -Vector::K, Vector::new(0.48, -0.6, 0.64)] {
+            let frame = Frame::from_normal(normal);
+            assert!((frame.s * frame.t).abs() < 1e-6 && (frame.s * frame.n).abs() < 1e-6);
+            assert!((frame.s.norm() - 1.).abs() < 1e-6 && (frame.t.norm() - 1.).abs() < 1e-6);
+            let v = Vector::new(0.3, -0.2, 0.5);
+            assert!((frame.to_world(frame.to_local(v)) - v).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn lambertian_samples() {
+        let normal = Vector::new(0., 0.6, 0.8);
+        let bsdf = LambertianBSDF {
+            frame: Frame::from_normal(normal),
+            albedo: Color::new(0.8, 0.5, 0.2),
+        };
+
+        // From either side the reflected fraction is the albedo
+        for wo in [Vector::new(0.6, 0., 0.8), Vector::new(0.6, 0., -0.8)] {
+            let n = 64;
+            let mut reflected = Color::BLACK;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = (
+                        (i as Float + 0.5) / n as Float,
+                        (j as Float + 0.5) / n as Float,
+                    );
+                    let sample = bsdf.sample(wo, u).unwrap();
+                    assert!((sample.direction * normal) * (wo * normal) > 0.);
+                    assert!((sample.pdf - bsdf.pdf(wo, sample.direction)).abs() < 1e-6);
+                    assert_eq!(sample.value, bsdf.evaluate(wo, sample.direction));
+                    reflected += sample.weight(normal);
+                }
+            }
+            let error = reflected / (n * n) as Float - bsdf.albedo;
+            assert!(error.red.abs().max(error.green.abs()).max(error.blue.abs()) < 1e-3);
+        }
+
+        // No transmission
+        let (wo, wi) = (Vector::new(0.6, 0., 0.8), -normal);
+        assert_eq!(bsdf.evaluate(wo, wi), Color::BLACK);
+        assert_eq!(bsdf.pdf(wo, wi), 0.);
+    }
+}
@@ -1,26 +1,27 @@
 use std::{
-    io::{Result, Write},
+    io::{BufRead, Error, ErrorKind, Result, Write},
     slice::IterMut,
 };
 
 use crate::color::Color;
+use crate::float::Float;
 
 // Basic sRGB and buffer implementation for headless projects
 
-const SRGB_GAMMA: f64 = 2.2;
-fn gamma_decode(encoded: f64) -> f64 {
+const SRGB_GAMMA: Float = 2.2;
+fn gamma_decode(encoded: Float) -> Float {
     encoded.powf(SRGB_GAMMA)
 }
-fn gamma_encode(linear: f64) -> f64 {
+fn gamma_encode(linear: Float) -> Float {
     linear.powf(1.0 / SRGB_GAMMA)
 }
 
 impl From<[u8; 3]> for Color {
     fn from(rgb: [u8; 3]) -> Self {
         Color {
-            red: gamma_decode((rgb[0] as f64) / 255.0),
-            green: gamma_decode((rgb[1] as f64) / 255.0),
-            blue: gamma_decode((rgb[2] as f64) / 255.0),
+            red: gamma_decode((rgb[0] as Float) / 255.0),
+            green: gamma_decode((rgb[1] as Float) / 255.0),
+            blue: gamma_decode((rgb[2] as Float) / 255.0),
         }
     }
 }
@@ -73,11 +74,11 @@ impl Buffer {
     pub fn get_lin(&self, idx: u32) -> [u8; 3] {
         self.data[idx as usize]
     }
-    pub fn get_mut<'a>(&'a mut self, x: u32, y: u32) -> &'a mut [u8; 3] {
+    pub fn get_mut(&mut self, x: u32, y: u32) -> &mut [u8; 3] {
         let idx = Self::cart_to_lin(self.height, x, y);
         self.data.get_mut(idx as usize).unwrap()
     }
-    pub fn get_lin_mut<'a>(&'a mut self, idx: u32) -> &'a mut [u8; 3] {
+    pub fn get_lin_mut(&mut self, idx: u32) -> &mut [u8; 3] {
         self.data.get_mut(idx as usize).unwrap()
     }
 
@@ -91,8 +92,8 @@ impl Buffer {
 
     pub fn write_binary_ppm<W: Write>(&self, out: &mut W) -> Result<()> {
         // Write header
-        write!(out, "P6\n")?;
-        write!(out, "{} {} {}\n", self.width, self.height, u8::MAX)?;
+        writeln!(out, "P6")?;
+        writeln!(out, "{} {} {}", self.width, self.height, u8::MAX)?;
         for y in 0..self.height {
             for x in 0..self.width {
                 out.write_all(self.get(x, y).as_slice())?;
@@ -101,14 +102,65 @@ impl Buffer {
         out.flush()
     }
 
+    // Only 8-bit binary files as written by write_binary_ppm
+    pub fn read_binary_ppm<R: BufRead>(input: &mut R) -> Result<Buffer> {
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+
+        // Header: magic, width, height and maximum value separated by whitespace,
+        // comments run to the end of the line
+        let mut fields = Vec::new();
+        while fields.len() < 4 {
+            let mut token = Vec::new();
+            loop {
+                let mut byte = [0];
+                input.read_exact(&mut byte)?;
+                match byte[0] {
+                    b'#' => {
+                        let mut comment = Vec::new();
+                        input.read_until(b'\n', &mut comment)?;
+                    }
+                    b if b.is_ascii_whitespace() => {
+                        if !token.is_empty() {
+                            break;
+                        }
+                    }
+                    b => token.push(b),
+                }
+            }
+            fields.push(String::from_utf8(token).map_err(|_| invalid("invalid header"))?);
+        }
+
+        if fields[0] != "P6" {
+            return Err(invalid("not a binary ppm"));
+        }
+        let parse = |field: &str| field.parse::<u32>().map_err(|_| invalid("invalid header"));
+        let (width, height) = (parse(&fields[1])?, parse(&fields[2])?);
+        if width.checked_mul(height).is_none() {
+            return Err(invalid("image too large"));
+        }
+        if parse(&fields[3])? != u8::MAX as u32 {
+            return Err(invalid("only 8-bit ppm files are supported"));
+        }
+
+        let mut buffer = Buffer::new(width, height);
+        let mut row = vec![0; 3 * width as usize];
+        for y in 0..height {
+            input.read_exact(&mut row)?;
+            for (x, rgb) in row.chunks(3).enumerate() {
+                *buffer.get_mut(x as u32, y) = [rgb[0], rgb[1], rgb[2]];
+            }
+        }
+        Ok(buffer)
+    }
+
     pub fn write_ascii_ppm<W: Write>(&self, out: &mut W) -> Result<()> {
         // Write header
-        write!(out, "P3\n")?;
-        write!(out, "{} {} {}\n", self.width, self.height, u8::MAX)?;
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {} {}", self.width, self.height, u8::MAX)?;
         for y in 0..self.height {
             for x in 0..self.width {
                 let rgb = self.get(x, y);
-                write!(out, "{} {} {}\n", rgb[0], rgb[1], rgb[2])?;
+                writeln!(out, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
             }
         }
         out.flush()
@@ -130,3 +182,30 @@ impl<'a> Iterator for EnumBufferMut<'a> {
         self.pixels.next().map(|p| (x, y, p))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_ppm_round_trip() {
+        let mut buffer = Buffer::new(3, 2);
+        for (x, y, pixel) in buffer.enum_iter_mut() {
+            *pixel = [x as u8, y as u8, 10 * x as u8 + y as u8];
+        }
+        let mut bytes = Vec::new();
+        buffer.write_binary_ppm(&mut bytes).unwrap();
+
+        let read = Buffer::read_binary_ppm(&mut bytes.as_slice()).unwrap();
+        assert_eq!((read.width(), read.height()), (3, 2));
+        assert_eq!(read.get(2, 1), [2, 1, 21]);
+    }
+
+    #[test]
+    fn binary_ppm_rejects_invalid_headers() {
+        for header in ["P6 70000 70000 255\n", "P3 1 1 255\n", "P6 1 1 65535\n"] {
+            let result = Buffer::read_binary_ppm(&mut header.as_bytes());
+            assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+        }
+    }
+}
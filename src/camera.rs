@@ -1,27 +1,54 @@
+use std::cell::Cell;
+
 #[cfg(feature = "parallel")]
 use rayon::iter::{ParallelBridge, ParallelIterator};
 
+use crate::aov::{AOVBuffer, AOV};
 use crate::buffer::Buffer;
 use crate::color::Color;
+use crate::float::Float;
 use crate::halton::Halton2Sequence;
 use crate::point::Point;
 use crate::ray::Ray;
 use crate::scene::Scene;
 use crate::vector::Vector;
 
+thread_local! {
+    // Apex and angle between neighbouring pixels of the cones through the pixel
+    // being rendered
+    static PIXEL_CONE: Cell<Option<(Point, Float)>> = const { Cell::new(None) };
+}
+
+// Width at the point of the cone through the pixel being rendered on this
+// thread, zero outside of rendering. Points reached after bounces get the width
+// at their distance from the camera, narrower than the cone of their path.
+pub fn pixel_footprint(point: Point) -> Float {
+    match PIXEL_CONE.with(|cone| cone.get()) {
+        Some((apex, spread)) => spread * (point - apex).norm(),
+        None => 0.,
+    }
+}
+
+// Angle between the rays through neighbouring pixels, zero outside of rendering
+pub fn pixel_spread() -> Float {
+    PIXEL_CONE
+        .with(|cone| cone.get())
+        .map_or(0., |(_, spread)| spread)
+}
+
 pub struct Camera {
     pub origin: Point,
-    pub azimuth: f64,
-    pub altitude: f64,
+    pub azimuth: Float,
+    pub altitude: Float,
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub fov: Float,
     pub spp: u32,
 }
 
 impl Camera {
-    fn create_prime(&self, x: f64, y: f64) -> Ray {
-        let aspect_ratio = (self.width as f64) / (self.height as f64);
+    fn create_prime(&self, x: Float, y: Float) -> Ray {
+        let aspect_ratio = (self.width as Float) / (self.height as Float);
         let sensor_direction = Vector {
             x: x * aspect_ratio,
             y,
@@ -37,21 +64,118 @@ impl Camera {
         }
     }
 
+    // Result of f with the cones through the pixels set, see pixel_footprint
+    fn with_pixel_cone<T>(&self, f: impl FnOnce() -> T) -> T {
+        // The image plane is at 1 / tan(fov), one unit high
+        let spread = self.fov.to_radians().tan() / self.height as Float;
+        let previous = PIXEL_CONE.with(|cone| cone.replace(Some((self.origin, spread))));
+        let result = f();
+        PIXEL_CONE.with(|cone| cone.set(previous));
+        result
+    }
+
     fn render_pixel(&self, scene: &Scene, px: u32, py: u32) -> Color {
         debug_assert!(px < self.width);
         debug_assert!(py < self.height);
 
-        let (dx, dy) = ((self.width as f64).recip(), (self.height as f64).recip());
-        let (x, y) = ((px as f64) * dx - 0.5, 0.5 - (py as f64) * dy);
+        let (dx, dy) = (
+            (self.width as Float).recip(),
+            (self.height as Float).recip(),
+        );
+        let (x, y) = ((px as Float) * dx - 0.5, 0.5 - (py as Float) * dy);
+
+        let color: Color = self.with_pixel_cone(|| {
+            Halton2Sequence::new(self.spp, 2, 3)
+                .map(|(prx, pry)| {
+                    scene.radiance(self.create_prime(x + prx / 2. * dx, y + pry / 2. * dy))
+                })
+                .sum()
+        });
+        color / self.spp as Float
+    }
+
+    fn render_pixel_aovs(
+        &self,
+        scene: &Scene,
+        px: u32,
+        py: u32,
+        aovs: &[AOV],
+        material_ids: &[Float],
+        pixel: &mut [Color],
+    ) {
+        debug_assert!(px < self.width);
+        debug_assert!(py < self.height);
+
+        let (dx, dy) = (
+            (self.width as Float).recip(),
+            (self.height as Float).recip(),
+        );
+        let (x, y) = ((px as Float) * dx - 0.5, 0.5 - (py as Float) * dy);
 
-        let scale_factor = (self.spp as f64).recip();
-        let mut color = Color::BLACK;
+        // Filterable passes are averaged like the beauty pass
+        let scale_factor = (self.spp as Float).recip();
         for (prx, pry) in Halton2Sequence::new(self.spp, 2, 3) {
-            color = color
-                + scale_factor
-                    * scene.color(self.create_prime(x + prx / 2. * dx, y + pry / 2. * dy), 0)
+            let ray = self.create_prime(x + prx / 2. * dx, y + pry / 2. * dy);
+            // Shared by the passes, the beauty pass continues the path from it
+            let inter = scene.trace(ray);
+
+            for (aov, value) in aovs.iter().zip(pixel.iter_mut()) {
+                if !aov.is_filterable() {
+                    continue;
+                }
+
+                let sample = match (aov, inter) {
+                    (AOV::Beauty, _) => scene.radiance_traced(ray, inter),
+                    (AOV::Normal, Some((_, object, hit))) => {
+                        let normal = object.material.shading(&hit).normal;
+                        Color::new(normal.x, normal.y, normal.z)
+                    }
+                    (AOV::Albedo, Some((_, object, hit))) => object.material.albedo(&hit),
+                    (AOV::Light(i), Some((_, object, hit))) => {
+                        match scene.light_sources().nth(*i) {
+                            Some(light) => {
+                                object.material.light_contribution(scene, light, &hit, ray)
+                            }
+                            None => Color::BLACK,
+                        }
+                    }
+                    _ => Color::BLACK,
+                };
+                *value += scale_factor * sample;
+            }
+        }
+
+        // Other passes only use the pixel center
+        let forward = self.create_prime(0., 0.).direction;
+        let ray = self.create_prime(x, y);
+        let inter = scene.trace_indexed(ray);
+
+        for (aov, value) in aovs.iter().zip(pixel.iter_mut()) {
+            if aov.is_filterable() {
+                continue;
+            }
+
+            *value = match (aov, inter) {
+                (AOV::Depth, Some((distance, _, _))) => {
+                    let depth = distance * (ray.direction * forward);
+                    Color::new(depth, depth, depth)
+                }
+                (AOV::Depth, None) => Color::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+                (AOV::Position, Some((_, _, hit))) => {
+                    Color::new(hit.point.x, hit.point.y, hit.point.z)
+                }
+                (AOV::ObjectId, Some((_, index, _))) => {
+                    let id = index as Float;
+                    Color::new(id, id, id)
+                }
+                (AOV::MaterialId, Some((_, index, _))) => {
+                    let id = material_ids[index];
+                    Color::new(id, id, id)
+                }
+                (AOV::ObjectId | AOV::MaterialId, None) => Color::new(-1., -1., -1.),
+                _ => Color::BLACK,
+            };
         }
-        color
     }
 
     pub fn render_section_into_buffer<'a, P, I>(
@@ -137,4 +261,128 @@ impl Camera {
     pub fn render(&self, scene: &Scene) -> Buffer {
         self.render_section(scene, 0, self.width, 0, self.height)
     }
+
+    pub fn render_aovs(&self, scene: &Scene, aovs: &[AOV]) -> AOVBuffer {
+        let mut buffer = AOVBuffer::new(self.width, self.height, aovs);
+        let material_ids = material_ids(scene);
+        let iter = buffer.enum_iter_mut();
+
+        #[cfg(feature = "parallel")]
+        let iter = iter.par_bridge();
+
+        iter.for_each(|(x, y, pixel)| {
+            self.with_pixel_cone(|| self.render_pixel_aovs(scene, x, y, aovs, &material_ids, pixel))
+        });
+        buffer
+    }
+}
+
+// Of each object, materials are numbered in order of first appearance
+fn material_ids(scene: &Scene) -> Vec<Float> {
+    let mut materials = Vec::new();
+    scene
+        .objects
+        .iter()
+        .map(|object| {
+            let id = object.material.id();
+            let index = match materials.iter().position(|&material| material == id) {
+                Some(index) => index,
+                None => {
+                    materials.push(id);
+                    materials.len() - 1
+                }
+            };
+            index as Float
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometries::Sphere;
+    use crate::integrator::Whitted;
+    use crate::materials::{Lambertian, None};
+    use crate::objects::Object;
+
+    fn two_spheres() -> Scene {
+        let sphere = |x: Float, color: Color| Object {
+            geometry: Box::new(Sphere {
+                center: Point::new(x, 0., -5.),
+                radius: 1.,
+            }),
+            material: Box::new(Lambertian {
+                color: Box::new(color),
+            }),
+            medium: Option::None,
+        };
+        Scene {
+            max_bounces: 1,
+            objects: vec![
+                sphere(0., Color::new(0.5, 0.5, 0.5)),
+                sphere(1.875, Color::new(0.2, 0.2, 0.2)),
+            ],
+            lights: vec![],
+            background: Box::new(None {}),
+            atmosphere: Option::None,
+            integrator: Box::new(Whitted {}),
+        }
+    }
+
+    // Pixel centers at x / 8 - 0.5 on an image plane at distance 1
+    const CAMERA: Camera = Camera {
+        origin: Point::ORIGIN,
+        azimuth: 0.,
+        altitude: 0.,
+        width: 8,
+        height: 8,
+        fov: 45.,
+        spp: 1,
+    };
+
+    #[test]
+    fn geometric_aovs() {
+        let scene = two_spheres();
+        let aovs = [AOV::Depth, AOV::ObjectId, AOV::MaterialId];
+        let buffer = CAMERA.render_aovs(&scene, &aovs);
+        let value = |aov, x, y| buffer.get(aov, x, y).unwrap().red;
+
+        // Straight ahead, at the front of the first sphere
+        assert!((value(AOV::Depth, 4, 4) - 4.).abs() < 1e-4);
+        assert_eq!(value(AOV::ObjectId, 4, 4), 0.);
+        assert_eq!(value(AOV::MaterialId, 4, 4), 0.);
+
+        // Through the center of the second sphere, the depth is along the axis
+        let cos = (1. + 0.375 as Float * 0.375).sqrt().recip();
+        assert!((value(AOV::Depth, 7, 4) - (5. - cos)).abs() < 1e-4);
+        assert_eq!(value(AOV::ObjectId, 7, 4), 1.);
+        assert_eq!(value(AOV::MaterialId, 7, 4), 1.);
+
+        assert_eq!(value(AOV::Depth, 0, 0), Float::INFINITY);
+        assert_eq!(value(AOV::ObjectId, 0, 0), -1.);
+        assert_eq!(value(AOV::MaterialId, 0, 0), -1.);
+    }
+
+    #[test]
+    fn footprint_of_pixel_cones() {
+        let scene = two_spheres();
+        let ray = Ray {
+            origin: Point::ORIGIN,
+            direction: -Vector::K,
+        };
+        assert_eq!(scene.trace(ray).unwrap().2.footprint, 0.);
+
+        // A pixel is 1 / 8 wide at distance 1, the sphere is hit at 4
+        let (_, _, hit) = CAMERA.with_pixel_cone(|| scene.trace(ray)).unwrap();
+        assert!((hit.footprint - 0.5).abs() < 1e-4);
+
+        // Stretched where the surface is seen at an angle
+        let ray = Ray {
+            origin: Point::ORIGIN,
+            direction: Vector::new(0.12, 0.16, -1.).normalize(),
+        };
+        let (distance, _, hit) = CAMERA.with_pixel_cone(|| scene.trace(ray)).unwrap();
+        let cos = ray.direction * hit.normal;
+        assert!((hit.footprint + distance / 8. / cos).abs() < 1e-4);
+    }
 }
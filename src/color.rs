@@ -1,10 +1,14 @@
-use std::ops::{Add, Mul, Sub};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
 
-#[derive(Copy, Clone, Debug)]
+use crate::float::Float;
+use crate::spectrum::Illuminant;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Color {
-    pub red: f64,
-    pub green: f64,
-    pub blue: f64,
+    pub red: Float,
+    pub green: Float,
+    pub blue: Float,
 }
 
 impl Color {
@@ -14,19 +18,25 @@ impl Color {
         blue: 0.,
     };
 
-    pub fn new(red: f64, green: f64, blue: f64) -> Color {
+    pub fn new(red: Float, green: Float, blue: Float) -> Color {
         Color { red, green, blue }
     }
 
+    // Linear sRGB of a black body at the temperature in Kelvin, with a
+    // luminance of 1. Panics unless the temperature is positive.
+    pub fn blackbody(temperature: Float) -> Color {
+        Illuminant::Blackbody(temperature).color()
+    }
+
     pub fn clamp(self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
         }
     }
 
-    pub fn powf(self, n: f64) -> Color {
+    pub fn powf(self, n: Float) -> Color {
         Color {
             red: self.red.powf(n),
             green: self.green.powf(n),
@@ -34,13 +44,40 @@ impl Color {
         }
     }
 
-    pub fn norm(self) -> f64 {
+    pub fn norm(self) -> Float {
         (self.red * self.red + self.green * self.green + self.blue * self.blue).sqrt()
     }
 
-    pub fn norm_squared(self) -> f64 {
+    pub fn norm_squared(self) -> Float {
         self.red * self.red + self.green * self.green + self.blue * self.blue
     }
+
+    // Relative luminance of linear sRGB
+    pub fn luminance(self) -> Float {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
+    pub fn max_component(self) -> Float {
+        self.red.max(self.green).max(self.blue)
+    }
+
+    pub fn is_black(self) -> bool {
+        self.red <= 0. && self.green <= 0. && self.blue <= 0.
+    }
+
+    // From self at t = 0 to other at t = 1
+    pub fn lerp(self, other: Color, t: Float) -> Color {
+        (1. - t) * self + t * other
+    }
+
+    pub fn has_nan(self) -> bool {
+        self.red.is_nan() || self.green.is_nan() || self.blue.is_nan()
+    }
+
+    // Neither NaN nor infinite, samples that aren't should be rejected
+    pub fn is_finite(self) -> bool {
+        self.red.is_finite() && self.green.is_finite() && self.blue.is_finite()
+    }
 }
 
 impl Mul for Color {
@@ -55,16 +92,16 @@ impl Mul for Color {
     }
 }
 
-impl Mul<Color> for f64 {
+impl Mul<Color> for Float {
     type Output = Color;
     fn mul(self, other: Color) -> Color {
         other * self
     }
 }
 
-impl Mul<f64> for Color {
+impl Mul<Float> for Color {
     type Output = Color;
-    fn mul(self, other: f64) -> Color {
+    fn mul(self, other: Float) -> Color {
         Color {
             red: self.red * other,
             green: self.green * other,
@@ -94,3 +131,67 @@ impl Sub for Color {
         }
     }
 }
+
+impl Div for Color {
+    type Output = Color;
+    fn div(self, other: Color) -> Color {
+        Color {
+            red: self.red / other.red,
+            green: self.green / other.green,
+            blue: self.blue / other.blue,
+        }
+    }
+}
+
+impl Div<Float> for Color {
+    type Output = Color;
+    fn div(self, other: Float) -> Color {
+        Color {
+            red: self.red / other,
+            green: self.green / other,
+            blue: self.blue / other,
+        }
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, other: Color) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Color {
+    fn sub_assign(&mut self, other: Color) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign for Color {
+    fn mul_assign(&mut self, other: Color) {
+        *self = *self * other;
+    }
+}
+
+impl MulAssign<Float> for Color {
+    fn mul_assign(&mut self, other: Float) {
+        *self = *self * other;
+    }
+}
+
+impl DivAssign<Float> for Color {
+    fn div_assign(&mut self, other: Float) {
+        *self = *self / other;
+    }
+}
+
+impl Sum for Color {
+    fn sum<I: Iterator<Item = Color>>(iter: I) -> Color {
+        iter.fold(Color::BLACK, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Color> for Color {
+    fn sum<I: Iterator<Item = &'a Color>>(iter: I) -> Color {
+        iter.copied().sum()
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+use crate::camera;
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::light::{Light, FAR};
+use crate::materials::Background;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampling::{random2, Distribution2D};
+use crate::scene::Scene;
+use crate::spectrum::{illuminant, reflectance};
+use crate::texture::{spherical_uv, Filter, ImageTexture, Wrap};
+use crate::vector::Vector;
+
+// Inverse of spherical_uv
+fn uv_direction((u, v): (Float, Float)) -> Vector {
+    let (sin_phi, cos_phi) = (2. * PI * (u - 0.5)).sin_cos();
+    let (sin_lat, cos_lat) = (PI * (v - 0.5)).sin_cos();
+    Vector::new(cos_lat * sin_phi, sin_lat, cos_lat * cos_phi)
+}
+
+// Equirectangular radiance map at infinity, also a light source whose
+// directions are importance sampled by luminance
+pub struct EnvironmentMap {
+    texture: ImageTexture,
+    distribution: Distribution2D,
+    // Degrees around the y-axis
+    pub rotation: Float,
+    pub intensity: Float,
+}
+
+impl EnvironmentMap {
+    // Linear radiance, row-major starting at the top left, the center of the
+    // image is in the +z direction
+    pub fn new(width: usize, height: usize, texels: Vec<Color>) -> EnvironmentMap {
+        // Rows near the poles cover a smaller solid angle
+        let weights: Vec<_> = texels
+            .iter()
+            .enumerate()
+            .map(|(i, &texel)| {
+                let v = 1. - ((i / width) as Float + 0.5) / height as Float;
+                texel.luminance().max(0.) * (PI * (v - 0.5)).cos()
+            })
+            .collect();
+
+        EnvironmentMap {
+            texture: ImageTexture::new(width, height, texels, Wrap::Repeat, Filter::Bilinear),
+            distribution: Distribution2D::new(width, height, &weights),
+            rotation: 0.,
+            intensity: 1.,
+        }
+    }
+
+    // Radiance (.hdr) files with RGBE pixels, either flat or run-length encoded
+    pub fn read_hdr<R: BufRead>(input: &mut R) -> Result<EnvironmentMap> {
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+        let mut line = String::new();
+
+        // Header: magic and variables up to an empty line, then the resolution
+        input.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid("not a radiance file"));
+        }
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("missing resolution"));
+            }
+            let variable = line.trim();
+            if variable.is_empty() {
+                break;
+            }
+            if variable.starts_with("FORMAT=") && variable != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid("only rgbe files are supported"));
+            }
+        }
+
+        line.clear();
+        input.read_line(&mut line)?;
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid("only -Y +X orientation is supported"));
+        }
+        let parse = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid resolution"))
+        };
+        let (height, width) = (parse(fields[1])?, parse(fields[3])?);
+        if width == 0 || height == 0 {
+            return Err(invalid("empty image"));
+        }
+        let size = width
+            .checked_mul(height)
+            .and_then(|texels| texels.checked_mul(std::mem::size_of::<Color>()));
+        if !matches!(size, Some(size) if size <= isize::MAX as usize) {
+            return Err(invalid("image too large"));
+        }
+
+        let mut texels = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_scanline(input, &mut scanline)?;
+            texels.extend(scanline.iter().map(|&rgbe| rgbe_color(rgbe)));
+        }
+        Ok(EnvironmentMap::new(width, height, texels))
+    }
+
+    fn rotate(&self, direction: Vector, angle: Float) -> Vector {
+        let (s, c) = angle.to_radians().sin_cos();
+        Vector::new(
+            c * direction.x + s * direction.z,
+            direction.y,
+            c * direction.z - s * direction.x,
+        )
+    }
+
+    // Filtered to the angle between pixels, u spans 2π
+    pub fn radiance(&self, direction: Vector) -> Color {
+        let uv = spherical_uv(self.rotate(direction, -self.rotation));
+        let width = camera::pixel_spread() / (2. * PI);
+        self.intensity * self.texture.lookup(uv, width)
+    }
+}
+
+fn rgbe_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::BLACK;
+    }
+    let scale = Float::powi(2., e as i32 - (128 + 8));
+    Color::new(
+        (r as Float + 0.5) * scale,
+        (g as Float + 0.5) * scale,
+        (b as Float + 0.5) * scale,
+    )
+}
+
+fn read_scanline<R: BufRead>(input: &mut R, scanline: &mut [[u8; 4]]) -> Result<()> {
+    let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+    let width = scanline.len();
+
+    let mut first = [0; 4];
+    input.read_exact(&mut first)?;
+    let rle =
+        first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0 && (8..0x8000).contains(&width);
+    if !rle {
+        scanline[0] = first;
+        for pixel in scanline.iter_mut().skip(1) {
+            input.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid("scanline width mismatch"));
+    }
+
+    // Each channel is stored separately as runs and literal sequences
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0; 1];
+            input.read_exact(&mut count)?;
+            let (run, count) = if count[0] > 128 {
+                (true, count[0] as usize - 128)
+            } else {
+                (false, count[0] as usize)
+            };
+            if count == 0 || x + count > width {
+                return Err(invalid("invalid run length"));
+            }
+            if run {
+                let mut value = [0; 1];
+                input.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                for pixel in &mut scanline[x..x + count] {
+                    let mut value = [0; 1];
+                    input.read_exact(&mut value)?;
+                    pixel[channel] = value[0];
+                }
+            }
+            x += count;
+        }
+    }
+    Ok(())
+}
+
+impl Background for EnvironmentMap {
+    fn background_color(&self, _scene: &Scene, ray: Ray) -> Color {
+        self.radiance(ray.direction)
+    }
+
+    fn light(&self) -> Option<&dyn Light> {
+        Some(self)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let ((x, y), pdf) = self.distribution.sample(random2());
+        // Rows start at the top, v is up
+        let v = 1. - y;
+        let direction = self.rotate(uv_direction((x, v)), self.rotation);
+        let far = point + FAR * direction;
+        let to_obj = Ray {
+            origin: far,
+            direction: -direction,
+        };
+
+        // From the image to the sphere of directions
+        let pdf = pdf / (2. * PI * PI * (PI * (v - 0.5)).cos());
+        if !(pdf > 0. && pdf.is_finite()) {
+            return (Color::BLACK, to_obj);
+        }
+        let visibility = reflectance(scene.see(point, far));
+        (
+            illuminant(self.radiance(direction)) * visibility * pdf.recip(),
+            to_obj,
+        )
+    }
+
+    fn pdf(&self, _point: Point, direction: Vector) -> Float {
+        let (u, v) = spherical_uv(self.rotate(direction, -self.rotation));
+        let cos = (PI * (v - 0.5)).cos();
+        if cos <= 0. {
+            return 0.;
+        }
+        self.distribution.pdf((u, 1. - v)) / (2. * PI * PI * cos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(header: &str, pixels: &[u8]) -> Result<EnvironmentMap> {
+        let mut file = header.as_bytes().to_vec();
+        file.extend_from_slice(pixels);
+        EnvironmentMap::read_hdr(&mut &file[..])
+    }
+
+    #[test]
+    fn flat_hdr() {
+        let header = "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n";
+        let map = read(header, &[128, 64, 0, 129, 0, 0, 0, 0]).unwrap();
+        let color = map.texture.lookup((0.25, 0.5), 0.);
+        assert!((color.red - 1.00390625).abs() < 1e-6);
+        assert!((color.green - 0.50390625).abs() < 1e-6);
+        assert_eq!(map.texture.lookup((0.75, 0.5), 0.).red, 0.);
+    }
+
+    #[test]
+    fn hdr_rejects_invalid_sizes() {
+        let huge = format!("-Y 8 +X {}", usize::MAX / 4);
+        for resolution in ["-Y 1 +X 0", "-Y 0 +X 4", "-Y 0 +X 0", &huge] {
+            let header = format!("#?RADIANCE\n\n{}\n", resolution);
+            let error = read(&header, &[0; 64]).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", resolution);
+        }
+        let error = read("#?RADIANCE\n\n-Y 2 +X 2\n", &[1, 1, 1, 129])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+}
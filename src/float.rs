@@ -0,0 +1,83 @@
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+// Scalar type of all geometry and colors. The f32 feature halves the memory of
+// meshes and buffers, SDFs are evaluated in double precision regardless.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+// Components of the math types, f32 or f64
+pub trait Scalar:
+    Copy
+    + Debug
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Rem<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn recip(self) -> Self;
+    fn rem_euclid(self, other: Self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+}
+
+macro_rules! scalar {
+    ($t:ty) => {
+        impl Scalar for $t {
+            const ZERO: $t = 0.;
+            const ONE: $t = 1.;
+
+            fn from_f64(x: f64) -> $t {
+                x as $t
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn sqrt(self) -> $t {
+                <$t>::sqrt(self)
+            }
+
+            fn abs(self) -> $t {
+                <$t>::abs(self)
+            }
+
+            fn max(self, other: $t) -> $t {
+                <$t>::max(self, other)
+            }
+
+            fn recip(self) -> $t {
+                <$t>::recip(self)
+            }
+
+            fn rem_euclid(self, other: $t) -> $t {
+                <$t>::rem_euclid(self, other)
+            }
+
+            fn sin_cos(self) -> ($t, $t) {
+                <$t>::sin_cos(self)
+            }
+        }
+    };
+}
+
+scalar!(f32);
+scalar!(f64);
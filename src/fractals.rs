@@ -7,7 +7,7 @@ pub struct SDFMengerSponge {
 
 // See https://iquilezles.org/articles/menger/
 impl SDF for SDFMengerSponge {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         let p = point.into_vector();
 
         // Start with cube
@@ -34,6 +34,6 @@ impl SDF for SDFMengerSponge {
             d = d.max(-c)
         }
 
-        return d;
+        d
     }
 }
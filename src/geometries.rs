@@ -1,23 +1,196 @@
+use crate::bsdf::Frame;
+use crate::float::consts::PI;
+use crate::float::Float;
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sampling::{concentric_disk, uniform_cone, uniform_sphere};
+use crate::texture::spherical_uv;
 use crate::vector::Vector;
 
+// Surface information at an intersection, as passed to materials and textures
+#[derive(Copy, Clone, Debug)]
+pub struct Hit {
+    pub point: Point,
+    pub normal: Vector,
+    pub uv: (Float, Float),
+    // Partial derivatives of the point with respect to the texture coordinates
+    pub dpdu: Vector,
+    pub dpdv: Vector,
+    // Width of the ray cone at the point, zero when unknown. Set by Scene::trace,
+    // see camera::pixel_footprint.
+    pub footprint: Float,
+}
+
 pub trait Geometry: Sync {
-    fn intersect(&self, ray: Ray) -> Option<f64>;
+    fn intersect(&self, ray: Ray) -> Option<Float>;
     fn surface_normal(&self, point: Point) -> Vector;
+
+    // With the index of the part hit, for geometries made of several like Mesh
+    fn intersect_part(&self, ray: Ray) -> Option<(Float, usize)> {
+        self.intersect(ray).map(|distance| (distance, 0))
+    }
+
+    // Hit on a part found by intersect_part
+    fn part_hit(&self, point: Point, _part: usize) -> Hit {
+        self.hit(point)
+    }
+
+    // Texture coordinates, constant unless the geometry has a parametrization
+    fn uv(&self, _point: Point) -> (Float, Float) {
+        (0., 0.)
+    }
+
+    // Any tangent frame will do without a parametrization
+    fn tangents(&self, point: Point) -> (Vector, Vector) {
+        self.surface_normal(point).orthonormals()
+    }
+
+    // Point on the surface as seen from the reference point, the pdf is with respect
+    // to solid angle at the reference. Needed for geometries used as lights.
+    fn sample(&self, _reference: Point, _u: (Float, Float)) -> Option<SurfaceSample> {
+        None
+    }
+
+    // Pdf of sample choosing the point seen from the reference in the direction,
+    // zero if the direction misses or the geometry can't be sampled
+    fn pdf(&self, _reference: Point, _direction: Vector) -> Float {
+        0.
+    }
+
+    // Axis aligned box as the min and max corners, None if unbounded
+    fn bounds(&self) -> Option<(Point, Point)> {
+        None
+    }
+
+    fn area(&self) -> Float {
+        0.
+    }
+
+    // Axis and cosine of the half angle of a cone containing all normals
+    fn normal_cone(&self) -> (Vector, Float) {
+        (Vector::K, -1.)
+    }
+
+    fn hit(&self, point: Point) -> Hit {
+        let (dpdu, dpdv) = self.tangents(point);
+        Hit {
+            point,
+            normal: self.surface_normal(point),
+            uv: self.uv(point),
+            dpdu,
+            dpdv,
+            footprint: 0.,
+        }
+    }
 }
 
-const BIAS: f64 = 4.0 * f64::EPSILON;
+#[derive(Copy, Clone, Debug)]
+pub struct SurfaceSample {
+    pub point: Point,
+    pub normal: Vector,
+    pub pdf: Float,
+    // Part of the geometry the point is on, as returned by intersect_part
+    pub part: usize,
+}
+
+// Converts a point sampled uniformly over the given area to a solid angle sample
+fn area_sample(
+    reference: Point,
+    point: Point,
+    normal: Vector,
+    area: Float,
+) -> Option<SurfaceSample> {
+    let to_point = point - reference;
+    let cos = (to_point.normalize() * normal).abs();
+    if cos == 0. || area == 0. {
+        return None;
+    }
+
+    Some(SurfaceSample {
+        point,
+        normal,
+        pdf: to_point.norm_squared() / (cos * area),
+        part: 0,
+    })
+}
+
+// Smallest box containing the points
+pub fn point_bounds(points: impl IntoIterator<Item = Point>) -> Option<(Point, Point)> {
+    points.into_iter().fold(Option::None, |bounds, p| {
+        let (min, max) = bounds.unwrap_or((p, p));
+        Some((
+            Point::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+            Point::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+        ))
+    })
+}
+
+// Part of the ray inside the box before the distance, if any
+pub(crate) fn box_interval(
+    min: Point,
+    max: Point,
+    ray: Ray,
+    distance: Float,
+) -> Option<(Float, Float)> {
+    let (mut near, mut far) = (0., distance);
+    let axes = [
+        (ray.origin.x, ray.direction.x, min.x, max.x),
+        (ray.origin.y, ray.direction.y, min.y, max.y),
+        (ray.origin.z, ray.direction.z, min.z, max.z),
+    ];
+    for (origin, direction, lo, hi) in axes {
+        if direction == 0. {
+            if origin < lo || origin > hi {
+                return None;
+            }
+            continue;
+        }
+        let (t0, t1) = ((lo - origin) / direction, (hi - origin) / direction);
+        near = t0.min(t1).max(near);
+        far = t0.max(t1).min(far);
+        if near > far {
+            return None;
+        }
+    }
+    Some((near, far))
+}
+
+// Pdf of area_sample for the point seen in the direction
+fn area_pdf<G: Geometry + ?Sized>(
+    geometry: &G,
+    reference: Point,
+    direction: Vector,
+    area: Float,
+) -> Float {
+    let ray = Ray {
+        origin: reference,
+        direction: direction.normalize(),
+    };
+    geometry
+        .intersect_part(ray)
+        .and_then(|(distance, part)| {
+            let point = reference + distance * ray.direction;
+            area_sample(
+                reference,
+                point,
+                geometry.part_hit(point, part).normal,
+                area,
+            )
+        })
+        .map_or(0., |sample| sample.pdf)
+}
+
+const BIAS: Float = 4.0 * Float::EPSILON;
 
 pub struct Sphere {
     pub center: Point,
-    pub radius: f64,
+    pub radius: Float,
 }
 
 impl Geometry for Sphere {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
         // Allows for optimizations
-        debug_assert!((ray.direction.norm() - 1.).abs() <= 4.0 * f64::EPSILON);
+        debug_assert!((ray.direction.norm() - 1.).abs() <= 4.0 * Float::EPSILON);
 
         let l = ray.origin - self.center;
         let b = ray.direction * l;
@@ -27,13 +200,16 @@ impl Geometry for Sphere {
 
         if d > 0. {
             let t = b.abs() + d.sqrt();
-            if b > 0. || c < 0. {
+            if c < 0. {
+                // Inside the sphere, use the far intersection
+                Some(d.sqrt() - b)
+            } else if b > 0. {
                 // Negative distance
                 None
             } else {
                 Some(c / t)
             }
-        } else if b <= 0. && d.abs() <= f64::EPSILON {
+        } else if b <= 0. && d.abs() <= Float::EPSILON {
             Some(-b)
         } else {
             None
@@ -43,6 +219,79 @@ impl Geometry for Sphere {
     fn surface_normal(&self, point: Point) -> Vector {
         (point - self.center).normalize()
     }
+
+    fn uv(&self, point: Point) -> (Float, Float) {
+        spherical_uv(point - self.center)
+    }
+
+    // Uniform over the cone of directions towards the sphere when outside
+    fn sample(&self, reference: Point, u: (Float, Float)) -> Option<SurfaceSample> {
+        let to_center = self.center - reference;
+        let distance2 = to_center.norm_squared();
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            let normal = uniform_sphere(u);
+            let area = 4. * PI * radius2;
+            return area_sample(reference, self.center + self.radius * normal, normal, area);
+        }
+
+        let cos_max = (1. - radius2 / distance2).max(0.).sqrt();
+        if cos_max >= 1. {
+            return None;
+        }
+        let direction = Frame::from_normal(to_center).to_world(uniform_cone(u, cos_max));
+
+        // Nearest intersection, grazing directions touch the silhouette
+        let b = direction * -to_center;
+        let t = -b - (b * b - distance2 + radius2).max(0.).sqrt();
+        let point = reference + t * direction;
+        Some(SurfaceSample {
+            point,
+            normal: (point - self.center).normalize(),
+            pdf: (2. * PI * (1. - cos_max)).recip(),
+            part: 0,
+        })
+    }
+
+    fn pdf(&self, reference: Point, direction: Vector) -> Float {
+        let distance2 = (self.center - reference).norm_squared();
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            return area_pdf(self, reference, direction, 4. * PI * radius2);
+        }
+
+        let cos_max = (1. - radius2 / distance2).max(0.).sqrt();
+        let ray = Ray {
+            origin: reference,
+            direction: direction.normalize(),
+        };
+        if cos_max >= 1. || self.intersect(ray).is_none() {
+            return 0.;
+        }
+        (2. * PI * (1. - cos_max)).recip()
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        let r = Vector::new(self.radius, self.radius, self.radius);
+        Some((self.center - r, self.center + r))
+    }
+
+    fn area(&self) -> Float {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn tangents(&self, point: Point) -> (Vector, Vector) {
+        let d = point - self.center;
+        let rho = d.x.hypot(d.z);
+        if rho < BIAS * self.radius {
+            // At the poles
+            return d.orthonormals();
+        }
+        (
+            2. * PI * Vector::new(d.z, 0., -d.x),
+            PI * Vector::new(-d.x * d.y / rho, rho, -d.z * d.y / rho),
+        )
+    }
 }
 
 pub struct Plane {
@@ -51,7 +300,7 @@ pub struct Plane {
 }
 
 impl Geometry for Plane {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
         let denom = ray.direction * self.normal;
         if denom.abs() > BIAS {
             let oo = self.origin - ray.origin;
@@ -66,4 +315,398 @@ impl Geometry for Plane {
     fn surface_normal(&self, _point: Point) -> Vector {
         self.normal.normalize()
     }
+
+    // Distances from the origin along two orthogonal in-plane axes
+    fn uv(&self, point: Point) -> (Float, Float) {
+        let (s, t) = self.normal.orthonormals();
+        let d = point - self.origin;
+        (d * s, d * t)
+    }
+}
+
+// Parallelogram spanned by two edges from a corner, the normal follows edge1 × edge2
+pub struct Rectangle {
+    pub corner: Point,
+    pub edge1: Vector,
+    pub edge2: Vector,
+}
+
+impl Rectangle {
+    // Coordinates of a point in the plane along the edges
+    fn coordinates(&self, point: Point) -> (Float, Float) {
+        let d = point - self.corner;
+        let (e11, e12, e22) = (
+            self.edge1 * self.edge1,
+            self.edge1 * self.edge2,
+            self.edge2 * self.edge2,
+        );
+        let (d1, d2) = (d * self.edge1, d * self.edge2);
+        let det = e11 * e22 - e12 * e12;
+        ((d1 * e22 - d2 * e12) / det, (d2 * e11 - d1 * e12) / det)
+    }
+}
+
+impl Geometry for Rectangle {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
+        let normal = self.edge1.cross(self.edge2);
+        let denom = ray.direction * normal;
+        if denom.abs() <= BIAS {
+            return None;
+        }
+
+        let d = ((self.corner - ray.origin) * normal) / denom;
+        let (s, t) = self.coordinates(ray.origin + d * ray.direction);
+        if d >= 0. && (0. ..=1.).contains(&s) && (0. ..=1.).contains(&t) {
+            Some(d)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _point: Point) -> Vector {
+        self.edge1.cross(self.edge2).normalize()
+    }
+
+    fn uv(&self, point: Point) -> (Float, Float) {
+        self.coordinates(point)
+    }
+
+    fn tangents(&self, _point: Point) -> (Vector, Vector) {
+        (self.edge1, self.edge2)
+    }
+
+    fn sample(&self, reference: Point, u: (Float, Float)) -> Option<SurfaceSample> {
+        let normal = self.edge1.cross(self.edge2);
+        let point = self.corner + u.0 * self.edge1 + u.1 * self.edge2;
+        area_sample(reference, point, normal.normalize(), normal.norm())
+    }
+
+    fn pdf(&self, reference: Point, direction: Vector) -> Float {
+        area_pdf(self, reference, direction, self.area())
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        let (c, e1, e2) = (self.corner, self.edge1, self.edge2);
+        point_bounds([c, c + e1, c + e2, c + e1 + e2])
+    }
+
+    fn area(&self) -> Float {
+        self.edge1.cross(self.edge2).norm()
+    }
+
+    fn normal_cone(&self) -> (Vector, Float) {
+        (self.edge1.cross(self.edge2).normalize(), 1.)
+    }
+}
+
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vector,
+    pub radius: Float,
+}
+
+impl Geometry for Disk {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
+        let denom = ray.direction * self.normal;
+        if denom.abs() <= BIAS {
+            return None;
+        }
+
+        let d = ((self.center - ray.origin) * self.normal) / denom;
+        let offset = ray.origin + d * ray.direction - self.center;
+        if d >= 0. && offset.norm_squared() <= self.radius * self.radius {
+            Some(d)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _point: Point) -> Vector {
+        self.normal.normalize()
+    }
+
+    fn sample(&self, reference: Point, u: (Float, Float)) -> Option<SurfaceSample> {
+        let frame = Frame::from_normal(self.normal);
+        let (x, y) = concentric_disk(u);
+        let point = self.center + self.radius * frame.to_world(Vector::new(x, y, 0.));
+        let area = PI * self.radius * self.radius;
+        area_sample(reference, point, frame.n, area)
+    }
+
+    fn pdf(&self, reference: Point, direction: Vector) -> Float {
+        area_pdf(self, reference, direction, self.area())
+    }
+
+    // Extent along each axis is the radius times the sine to the normal
+    fn bounds(&self) -> Option<(Point, Point)> {
+        let n = self.normal.normalize();
+        let extent = |c: Float| self.radius * (1. - c * c).max(0.).sqrt();
+        let r = Vector::new(extent(n.x), extent(n.y), extent(n.z));
+        Some((self.center - r, self.center + r))
+    }
+
+    fn area(&self) -> Float {
+        PI * self.radius * self.radius
+    }
+
+    fn normal_cone(&self) -> (Vector, Float) {
+        (self.normal.normalize(), 1.)
+    }
+}
+
+// The normal follows the counterclockwise winding a, b, c
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    pub a: Point,
+    pub b: Point,
+    pub c: Point,
+}
+
+impl Triangle {
+    // Barycentric coordinates of b and c
+    fn barycentric(&self, point: Point) -> (Float, Float) {
+        let (e1, e2, d) = (self.b - self.a, self.c - self.a, point - self.a);
+        let (e11, e12, e22) = (e1 * e1, e1 * e2, e2 * e2);
+        let (d1, d2) = (d * e1, d * e2);
+        let det = e11 * e22 - e12 * e12;
+        ((d1 * e22 - d2 * e12) / det, (d2 * e11 - d1 * e12) / det)
+    }
+
+    // Distance to the triangle, approximating the nearest point by clamping the
+    // barycentric coordinates. NaN for degenerate triangles.
+    fn distance(&self, point: Point) -> Float {
+        let (b1, b2) = self.barycentric(point);
+        let (b1, b2) = (b1.max(0.), b2.max(0.));
+        let sum = b1 + b2;
+        let (b1, b2) = if sum > 1. {
+            (b1 / sum, b2 / sum)
+        } else {
+            (b1, b2)
+        };
+        (point - (self.a + b1 * (self.b - self.a) + b2 * (self.c - self.a))).norm()
+    }
+}
+
+impl Geometry for Triangle {
+    // Möller-Trumbore
+    fn intersect(&self, ray: Ray) -> Option<Float> {
+        let (e1, e2) = (self.b - self.a, self.c - self.a);
+        let p = ray.direction.cross(e2);
+        let det = e1 * p;
+        if det.abs() <= BIAS {
+            return None;
+        }
+
+        let s = ray.origin - self.a;
+        let b1 = (s * p) / det;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let b2 = (ray.direction * q) / det;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+
+        let d = (e2 * q) / det;
+        if d >= 0. {
+            Some(d)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _point: Point) -> Vector {
+        (self.b - self.a).cross(self.c - self.a).normalize()
+    }
+
+    fn uv(&self, point: Point) -> (Float, Float) {
+        self.barycentric(point)
+    }
+
+    fn tangents(&self, _point: Point) -> (Vector, Vector) {
+        (self.b - self.a, self.c - self.a)
+    }
+
+    fn sample(&self, reference: Point, u: (Float, Float)) -> Option<SurfaceSample> {
+        let su = u.0.sqrt();
+        let (b1, b2) = (su * (1. - u.1), su * u.1);
+        let point = self.a + b1 * (self.b - self.a) + b2 * (self.c - self.a);
+        area_sample(reference, point, self.surface_normal(point), self.area())
+    }
+
+    fn pdf(&self, reference: Point, direction: Vector) -> Float {
+        area_pdf(self, reference, direction, self.area())
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        point_bounds([self.a, self.b, self.c])
+    }
+
+    fn area(&self) -> Float {
+        (self.b - self.a).cross(self.c - self.a).norm() / 2.
+    }
+
+    fn normal_cone(&self) -> (Vector, Float) {
+        (self.surface_normal(self.a), 1.)
+    }
+}
+
+// Triangle soup without an acceleration structure, meant for small meshes
+pub struct Mesh {
+    triangles: Vec<Triangle>,
+    // Cumulative triangle areas, to sample points uniformly
+    cdf: Vec<Float>,
+}
+
+impl Mesh {
+    pub fn new(vertices: &[Point], faces: &[[usize; 3]]) -> Mesh {
+        let triangles: Vec<Triangle> = faces
+            .iter()
+            .map(|face| Triangle {
+                a: vertices[face[0]],
+                b: vertices[face[1]],
+                c: vertices[face[2]],
+            })
+            .collect();
+
+        let mut total = 0.;
+        let cdf = triangles
+            .iter()
+            .map(|triangle| {
+                total += triangle.area();
+                total
+            })
+            .collect();
+
+        Mesh { triangles, cdf }
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    // Nearest triangle to the point, a search over all of them. Hits found by
+    // intersect_part and samples know their triangle instead.
+    fn triangle(&self, point: Point) -> Option<&Triangle> {
+        self.triangles
+            .iter()
+            .map(|triangle| (triangle.distance(point), triangle))
+            .filter(|(distance, _)| !distance.is_nan())
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, triangle)| triangle)
+    }
+}
+
+impl Geometry for Mesh {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
+        self.intersect_part(ray).map(|(distance, _)| distance)
+    }
+
+    fn intersect_part(&self, ray: Ray) -> Option<(Float, usize)> {
+        self.triangles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, triangle)| triangle.intersect(ray).map(|distance| (distance, i)))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+    }
+
+    fn part_hit(&self, point: Point, part: usize) -> Hit {
+        self.triangles[part].hit(point)
+    }
+
+    fn surface_normal(&self, point: Point) -> Vector {
+        match self.triangle(point) {
+            Some(triangle) => triangle.surface_normal(point),
+            None => Vector::K,
+        }
+    }
+
+    fn tangents(&self, point: Point) -> (Vector, Vector) {
+        match self.triangle(point) {
+            Some(triangle) => triangle.tangents(point),
+            None => Vector::K.orthonormals(),
+        }
+    }
+
+    fn sample(&self, reference: Point, u: (Float, Float)) -> Option<SurfaceSample> {
+        let area = *self.cdf.last()?;
+        if area == 0. {
+            return None;
+        }
+
+        // Pick a triangle proportional to its area and reuse the remainder of u.0
+        let target = u.0 * area;
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.triangles.len() - 1);
+        let start = if i == 0 { 0. } else { self.cdf[i - 1] };
+        let u0 = ((target - start) / (self.cdf[i] - start)).clamp(0., 1.);
+
+        let triangle = &self.triangles[i];
+        let sample = triangle.sample(reference, (u0, u.1))?;
+        area_sample(reference, sample.point, sample.normal, area)
+            .map(|sample| SurfaceSample { part: i, ..sample })
+    }
+
+    fn pdf(&self, reference: Point, direction: Vector) -> Float {
+        area_pdf(self, reference, direction, self.area())
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        point_bounds(
+            self.triangles
+                .iter()
+                .flat_map(|triangle| [triangle.a, triangle.b, triangle.c]),
+        )
+    }
+
+    fn area(&self) -> Float {
+        self.cdf.last().copied().unwrap_or(0.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_samples_know_their_triangle() {
+        // A unit square facing up and a triangle facing the reference sideways
+        let vertices = [
+            Point::new(0., 0., 0.),
+            Point::new(1., 0., 0.),
+            Point::new(1., 1., 0.),
+            Point::new(0., 1., 0.),
+            Point::new(2., 0., 0.),
+            Point::new(2., 1., 0.),
+            Point::new(2., 0., 1.),
+        ];
+        let mesh = Mesh::new(&vertices, &[[0, 1, 2], [0, 2, 3], [4, 5, 6]]);
+        let reference = Point::new(0.25, 0.5, 2.);
+
+        let n = 8;
+        let mut parts = [0; 3];
+        for i in 0..n * n {
+            let u = (
+                ((i % n) as Float + 0.5) / n as Float,
+                ((i / n) as Float + 0.5) / n as Float,
+            );
+            let sample = mesh.sample(reference, u).unwrap();
+            parts[sample.part] += 1;
+            let triangle = &mesh.triangles()[sample.part];
+            assert!(triangle.distance(sample.point) < 1e-4);
+
+            let pdf = mesh.pdf(reference, sample.point - reference);
+            assert!(
+                (pdf / sample.pdf - 1.).abs() < 1e-3,
+                "{} {}",
+                pdf,
+                sample.pdf
+            );
+        }
+        // The triangles have the same area, the remainder of u.0 picks them
+        assert_eq!(parts, [24, 16, 24]);
+    }
 }
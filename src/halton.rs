@@ -1,3 +1,5 @@
+use crate::float::Float;
+
 pub struct HaltonSequence {
     index: u32,
     length: u32,
@@ -16,7 +18,7 @@ impl HaltonSequence {
 }
 
 impl Iterator for HaltonSequence {
-    type Item = f64;
+    type Item = Float;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index == self.length {
@@ -25,12 +27,12 @@ impl Iterator for HaltonSequence {
             let mut i = self.index;
             self.index += 1;
 
-            let mut f = 1 as f64;
-            let mut r = 0 as f64;
+            let mut f: Float = 1.;
+            let mut r: Float = 0.;
             while i > 0 {
-                f = f / self.base as f64;
-                r = r + f * (i % self.base) as f64;
-                i = i / self.base; // Integer division
+                f /= self.base as Float;
+                r += f * (i % self.base) as Float;
+                i /= self.base; // Integer division
             }
             Some(2.*r - 1.)
         }
@@ -53,7 +55,7 @@ impl Halton2Sequence {
 }
 
 impl Iterator for Halton2Sequence {
-    type Item = (f64, f64);
+    type Item = (Float, Float);
 
     fn next(&mut self) -> Option<Self::Item> {
         let a = self.seq1.next()?;
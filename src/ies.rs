@@ -0,0 +1,250 @@
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+use crate::float::consts::PI;
+use crate::float::Float;
+
+// Luminous intensity distribution of a luminaire from an IES LM-63 file, with
+// type C photometry: vertical angles from the nadir, horizontal angles around it
+pub struct IesProfile {
+    // In degrees, increasing
+    pub vertical_angles: Vec<Float>,
+    pub horizontal_angles: Vec<Float>,
+    // In candela, one row of vertical samples per horizontal angle
+    pub candela: Vec<Vec<Float>>,
+    // Rated flux of the lamps, None for absolute photometry of LED luminaires
+    pub lumens: Option<Float>,
+}
+
+// Index of the interval containing x and the position within it
+fn locate(angles: &[Float], x: Float) -> Option<(usize, Float)> {
+    let (first, last) = (angles[0], angles[angles.len() - 1]);
+    if angles.len() == 1 || x < first || x > last {
+        return if x == first { Some((0, 0.)) } else { None };
+    }
+    let i = (angles.partition_point(|&a| a <= x) - 1).min(angles.len() - 2);
+    let width = angles[i + 1] - angles[i];
+    Some((
+        i,
+        if width > 0. {
+            (x - angles[i]) / width
+        } else {
+            0.
+        },
+    ))
+}
+
+impl IesProfile {
+    // LM-63-1986 to 2019, tilt data is skipped
+    pub fn read<R: BufRead>(input: &mut R) -> Result<IesProfile> {
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+
+        // Keywords and labels up to the tilt line
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("missing TILT line"));
+            }
+            if let Some(tilt) = line.trim().strip_prefix("TILT=") {
+                if tilt != "NONE" && tilt != "INCLUDE" {
+                    return Err(invalid("external tilt files are not supported"));
+                }
+                break;
+            }
+        }
+        let tilt = line.trim() == "TILT=INCLUDE";
+
+        let mut rest = String::new();
+        input.read_to_string(&mut rest)?;
+        let mut values = rest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<Float>()
+                    .map_err(|_| invalid("invalid number"))
+            });
+        let mut next = || {
+            values
+                .next()
+                .unwrap_or(Err(invalid("unexpected end of file")))
+        };
+
+        if tilt {
+            // Lamp to luminaire geometry, then angles and multiplying factors
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let lamps = next()?;
+        let lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let (vertical, horizontal) = (next()? as usize, next()? as usize);
+        let photometric_type = next()?;
+        // Units and luminous opening dimensions
+        for _ in 0..4 {
+            next()?;
+        }
+        let ballast_factor = next()?;
+        // Future use and input watts
+        next()?;
+        next()?;
+
+        // -1 marks absolute photometry
+        let lumens = if lumens_per_lamp == -1. {
+            None
+        } else if lumens_per_lamp >= 0. {
+            Some(lamps * lumens_per_lamp)
+        } else {
+            return Err(invalid("negative lumens"));
+        };
+        if photometric_type != 1. {
+            return Err(invalid("only type C photometry is supported"));
+        }
+        if vertical == 0 || horizontal == 0 {
+            return Err(invalid("no angles"));
+        }
+
+        let vertical_angles = (0..vertical).map(|_| next()).collect::<Result<Vec<_>>>()?;
+        let horizontal_angles = (0..horizontal)
+            .map(|_| next())
+            .collect::<Result<Vec<_>>>()?;
+        let mut candela = Vec::with_capacity(horizontal);
+        for _ in 0..horizontal {
+            let row = (0..vertical)
+                .map(|_| Ok(next()? * multiplier * ballast_factor))
+                .collect::<Result<Vec<_>>>()?;
+            candela.push(row);
+        }
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            lumens,
+        })
+    }
+
+    // Luminous flux of the whole distribution, integrated numerically
+    pub fn flux(&self) -> Float {
+        let (rows, columns) = (180, 360);
+        let (dv, dh) = (PI / rows as Float, 2. * PI / columns as Float);
+        let mut flux = 0.;
+        for i in 0..rows {
+            let vertical = (i as Float + 0.5) * dv;
+            let row: Float = (0..columns)
+                .map(|j| {
+                    let horizontal = (j as Float + 0.5) * dh;
+                    self.candela(vertical.to_degrees(), horizontal.to_degrees())
+                })
+                .sum();
+            flux += row * vertical.sin() * dv * dh;
+        }
+        flux
+    }
+
+    pub fn max_candela(&self) -> Float {
+        self.candela.iter().flatten().fold(0., |max, &c| c.max(max))
+    }
+
+    // Bilinearly interpolated, zero outside the measured vertical range
+    pub fn candela(&self, vertical: Float, horizontal: Float) -> Float {
+        // The last horizontal angle gives the symmetry of the distribution
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        let mut horizontal = horizontal.rem_euclid(360.);
+        if last <= 180. && horizontal > 180. {
+            horizontal = 360. - horizontal;
+        }
+        if last <= 90. && horizontal > 90. {
+            horizontal = 180. - horizontal;
+        }
+
+        let Some((v, tv)) = locate(&self.vertical_angles, vertical) else {
+            return 0.;
+        };
+        let at = |row: &[Float]| {
+            if tv > 0. {
+                (1. - tv) * row[v] + tv * row[v + 1]
+            } else {
+                row[v]
+            }
+        };
+
+        if last == 0. {
+            return at(&self.candela[0]);
+        }
+        match locate(&self.horizontal_angles, horizontal) {
+            Some((h, th)) if th > 0. => {
+                (1. - th) * at(&self.candela[h]) + th * at(&self.candela[h + 1])
+            }
+            Some((h, _)) => at(&self.candela[h]),
+            // Full distributions may stop short of 360, wrap around to the first angle
+            None => {
+                let first = self.horizontal_angles[0] + 360.;
+                let t = ((horizontal - last) / (first - last)).clamp(0., 1.);
+                (1. - t) * at(&self.candela[self.candela.len() - 1]) + t * at(&self.candela[0])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] 1
+[MANUFAC] none
+TILT=NONE
+1 1000 2 3 2 1 1 0.5 0.5 0
+1.0 1.0 50
+0 45 90
+0 90
+100, 80, 0
+60, 40, 0
+";
+
+    #[test]
+    fn read() {
+        let profile = IesProfile::read(&mut PROFILE.as_bytes()).unwrap();
+        assert_eq!(profile.lumens, Some(1000.));
+        assert_eq!(profile.vertical_angles, vec![0., 45., 90.]);
+        assert_eq!(profile.horizontal_angles, vec![0., 90.]);
+        // Scaled by the multiplier
+        assert_eq!(
+            profile.candela,
+            vec![vec![200., 160., 0.], vec![120., 80., 0.]]
+        );
+        assert_eq!(profile.max_candela(), 200.);
+    }
+
+    #[test]
+    fn interpolate() {
+        let profile = IesProfile::read(&mut PROFILE.as_bytes()).unwrap();
+        assert_eq!(profile.candela(22.5, 0.), 180.);
+        assert_eq!(profile.candela(0., 45.), 160.);
+        // Quadrant symmetry
+        assert_eq!(profile.candela(45., 270.), 80.);
+        assert_eq!(profile.candela(45., 180.), 160.);
+        assert_eq!(profile.candela(120., 0.), 0.);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let other_photometry = PROFILE.replace("2 3 2 1", "2 3 2 2");
+        assert!(IesProfile::read(&mut other_photometry.as_bytes()).is_err());
+        assert!(IesProfile::read(&mut "TILT=NONE\n1 1000 2".as_bytes()).is_err());
+        assert!(IesProfile::read(&mut "no tilt".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn flux() {
+        let isotropic = "TILT=NONE\n1 -1 1 2 1 1 1 0 0 0\n1 1 0\n0 180\n0\n100 100\n";
+        let profile = IesProfile::read(&mut isotropic.as_bytes()).unwrap();
+        assert_eq!(profile.lumens, None);
+        assert!((profile.flux() / (400. * PI) - 1.).abs() < 1e-3);
+    }
+}
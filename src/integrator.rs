@@ -0,0 +1,367 @@
+use crate::bsdf::BSDF;
+use crate::color::Color;
+use crate::float::Float;
+use crate::geometries::Hit;
+use crate::light::Light;
+use crate::medium::HenyeyGreenstein;
+use crate::objects::Object;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampling::{power_heuristic, random, random2};
+use crate::scene::{Scene, SHADOW_EPSILON};
+use crate::spectrum::{self, illuminant, reflectance, Wavelengths};
+use crate::vector::Vector;
+
+pub trait Integrator: Sync {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Color;
+
+    // Radiance along a ray whose intersection is already known, saves tracing
+    // it again when rendering other passes
+    fn radiance_traced(
+        &self,
+        scene: &Scene,
+        ray: Ray,
+        _inter: Option<(Float, &Object, Hit)>,
+    ) -> Color {
+        self.radiance(scene, ray)
+    }
+
+    // Whether shadow rays may go straight through refracting surfaces, for
+    // integrators that can't find the caustics behind them otherwise
+    fn ignores_refraction(&self) -> bool {
+        false
+    }
+}
+
+const BIAS: Float = 32. * Float::EPSILON;
+
+// Offsets the point to the side of the surface the direction points to
+pub(crate) fn offset(point: Point, normal: Vector, direction: Vector) -> Point {
+    if direction * normal < 0. {
+        point - BIAS * normal
+    } else {
+        point + BIAS * normal
+    }
+}
+
+// Recursive ray tracing using Material::surface_color
+pub struct Whitted {}
+
+impl Integrator for Whitted {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Color {
+        scene.color(ray, 0)
+    }
+
+    fn ignores_refraction(&self) -> bool {
+        true
+    }
+}
+
+// Unidirectional path tracing with next event estimation. Colors along the
+// paths are upsampled to the current wavelengths when rendering spectrally.
+pub struct PathTracer {
+    pub max_depth: u32,
+    // Depth after which paths are terminated with Russian roulette
+    pub rr_depth: u32,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Color {
+        self.trace_path(scene, ray, None)
+    }
+
+    fn radiance_traced(
+        &self,
+        scene: &Scene,
+        ray: Ray,
+        inter: Option<(Float, &Object, Hit)>,
+    ) -> Color {
+        self.trace_path(scene, ray, Some(inter))
+    }
+}
+
+impl PathTracer {
+    // Starting from the intersection of the ray when given
+    fn trace_path<'a>(
+        &self,
+        scene: &'a Scene,
+        ray: Ray,
+        first: Option<Option<(Float, &'a Object, Hit)>>,
+    ) -> Color {
+        let mut first = first;
+        let mut color = Color::BLACK;
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = ray;
+        // Of the last bounce, None for camera rays and specular bounces
+        let mut bsdf_pdf = None;
+        // Whether the path went straight through transparent surfaces since the
+        // last non-specular bounce, whose shadow rays already reached the lights
+        let mut see_through = false;
+
+        for depth in 0..=self.max_depth {
+            let inter = first.take().unwrap_or_else(|| scene.trace(ray));
+            let light = scene
+                .trace_lights(ray)
+                .filter(|light| inter.is_none_or(|inter| light.0 < inter.0));
+            let weight = |light: Option<&dyn Light>| match light {
+                Some(light) if see_through && light.pdf(ray.origin, ray.direction) > 0. => 0.,
+                _ => emission_weight(light, ray, bsdf_pdf),
+            };
+
+            if let Some(medium) = scene.medium(ray, inter) {
+                let distance = light
+                    .map_or(inter.map_or(Float::INFINITY, |inter| inter.0), |light| {
+                        light.0
+                    });
+                let (scatter, weight) = medium.sample(ray, distance, throughput);
+                throughput *= reflectance(weight);
+                if let Some(t) = scatter {
+                    let point = ray.origin + t * ray.direction;
+                    let phase = medium.phase();
+                    let wo = -ray.direction;
+                    color += throughput * scattered_light(scene, &phase, point, wo);
+
+                    let wi = phase.sample(wo, random2());
+                    bsdf_pdf = Some(phase.evaluate(wo, wi));
+                    see_through = false;
+                    ray = Ray {
+                        origin: point,
+                        direction: wi,
+                    };
+                    if depth >= self.rr_depth && !survives_roulette(&mut throughput) {
+                        break;
+                    }
+                    continue;
+                }
+                // Absorbed
+                if throughput.is_black() {
+                    break;
+                }
+            }
+
+            // Light surfaces end the path
+            if let Some((_, radiance, light)) = light {
+                let weight = weight(Some(light));
+                color += throughput * radiance * weight;
+                break;
+            }
+
+            let (distance, object, hit) = match inter {
+                Some(inter) => inter,
+                None => {
+                    let weight = weight(scene.background.light());
+                    let background = scene.background.background_color(scene, ray);
+                    color += throughput * illuminant(background) * weight;
+                    break;
+                }
+            };
+            let (point, normal) = (hit.point, hit.normal);
+            let wo = -ray.direction;
+
+            let emitter = object
+                .material
+                .is_emissive()
+                .then_some(object as &dyn Light);
+            let weight = weight(emitter);
+
+            // Leaving the object, the ray travelled through its interior
+            if ray.direction * normal > 0. {
+                throughput *= reflectance(object.material.interior_transmittance(&hit, distance));
+            }
+
+            // Materials without a BSDF end the path
+            let bsdf = match object.material.bsdf(&hit) {
+                Some(bsdf) => bsdf,
+                // Shaded in RGB, like by Whitted, and upsampled as a whole
+                None => {
+                    let surface = spectrum::in_rgb(|| {
+                        object.material.surface_color(scene, &hit, ray, depth)
+                            + (weight - 1.) * object.material.emitted(&hit, ray)
+                    });
+                    color += throughput * illuminant(surface);
+                    break;
+                }
+            };
+            color += throughput * illuminant(object.material.emitted(&hit, ray)) * weight;
+            let shading_normal = object.material.shading(&hit).normal;
+
+            // Next event estimation
+            let direct = direct_lighting(scene, bsdf.as_ref(), point, shading_normal, wo);
+            color += throughput * direct;
+
+            if depth == self.max_depth {
+                break;
+            }
+
+            let sample = match bsdf.sample(wo, random2()) {
+                Some(sample) if sample.pdf > 0. => sample,
+                _ => break,
+            };
+            throughput *= reflectance(sample.weight(shading_normal));
+            let transmitted = (sample.direction * normal) * (wo * normal) < 0.;
+            let transparent = || {
+                !object
+                    .material
+                    .shadow_transmittance(&hit, sample.direction, false)
+                    .is_black()
+            };
+            see_through = sample.specular
+                && (see_through || bsdf_pdf.is_some())
+                && transmitted
+                && transparent();
+            bsdf_pdf = (!sample.specular).then_some(sample.pdf);
+            ray = Ray {
+                origin: offset(point, normal, sample.direction),
+                direction: sample.direction.normalize(),
+            };
+
+            if depth >= self.rr_depth && !survives_roulette(&mut throughput) {
+                break;
+            }
+        }
+
+        // Rejects samples broken by degenerate geometry or pdfs
+        if color.is_finite() {
+            color
+        } else {
+            Color::BLACK
+        }
+    }
+}
+
+// Path tracing at a few wavelengths per camera ray, converted to RGB at the film
+pub struct Spectral {
+    pub path_tracer: PathTracer,
+}
+
+impl Integrator for Spectral {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Color {
+        self.radiance_traced(scene, ray, scene.trace(ray))
+    }
+
+    fn radiance_traced(
+        &self,
+        scene: &Scene,
+        ray: Ray,
+        inter: Option<(Float, &Object, Hit)>,
+    ) -> Color {
+        let wavelengths = Wavelengths::sample(random());
+        let (samples, wavelengths) = spectrum::with_wavelengths(wavelengths, || {
+            self.path_tracer.radiance_traced(scene, ray, inter)
+        });
+        spectrum::to_rgb(samples, &wavelengths)
+    }
+}
+
+// Weight of emission found along a ray sampled from a BSDF with the given pdf,
+// against next event estimation of the same light with the power heuristic
+pub fn emission_weight(light: Option<&dyn Light>, ray: Ray, bsdf_pdf: Option<Float>) -> Float {
+    match (light, bsdf_pdf) {
+        (Some(light), Some(bsdf_pdf)) => {
+            let light_pdf = light.pdf(ray.origin, ray.direction);
+            if light_pdf > 0. {
+                power_heuristic(bsdf_pdf, light_pdf)
+            } else {
+                1.
+            }
+        }
+        _ => 1.,
+    }
+}
+
+// Russian roulette, scaling the throughput of surviving paths
+fn survives_roulette(throughput: &mut Color) -> bool {
+    let q = throughput.max_component().min(0.95);
+    if random() >= q {
+        return false;
+    }
+    *throughput /= q;
+    true
+}
+
+// Whether the shadow ray from the point to the light sample went through
+// surfaces. Paths sampled through them get no weight, see PathTracer, so such
+// light samples get all of it.
+fn seen_through(scene: &Scene, point: Point, light_ray: Ray) -> bool {
+    let distance = (light_ray.origin - point).norm();
+    let ray = Ray {
+        origin: point,
+        direction: -light_ray.direction,
+    };
+    scene
+        .trace(ray)
+        .is_some_and(|inter| inter.0 < (1. - SHADOW_EPSILON) * distance)
+}
+
+fn light_sample(
+    scene: &Scene,
+    light: &dyn Light,
+    bsdf: &dyn BSDF,
+    point: Point,
+    normal: Vector,
+    wo: Vector,
+) -> (Color, Ray) {
+    let (light_color, light_ray) = light.sample(scene, offset(point, normal, wo));
+    let wi = -light_ray.direction;
+    (
+        light_color * reflectance(bsdf.evaluate(wo, wi)) * (wi * normal).abs(),
+        light_ray,
+    )
+}
+
+// Light arriving from a single light, reflected towards wo
+pub fn sample_light(
+    scene: &Scene,
+    light: &dyn Light,
+    bsdf: &dyn BSDF,
+    point: Point,
+    normal: Vector,
+    wo: Vector,
+) -> Color {
+    light_sample(scene, light, bsdf, point, normal, wo).0
+}
+
+// Light samples from all lights, weighted against sampling the BSDF so it
+// should be combined with emission_weight. The BSDF is upsampled separately
+// from the light when rendering spectrally.
+pub fn direct_lighting(
+    scene: &Scene,
+    bsdf: &dyn BSDF,
+    point: Point,
+    normal: Vector,
+    wo: Vector,
+) -> Color {
+    let mut color = Color::BLACK;
+    let origin = offset(point, normal, wo);
+    for light in scene.light_sources() {
+        let (contribution, light_ray) = light_sample(scene, light, bsdf, point, normal, wo);
+        let wi = -light_ray.direction;
+        let light_pdf = light.pdf(origin, wi);
+        let weight = if light_pdf > 0. && !seen_through(scene, origin, light_ray) {
+            power_heuristic(light_pdf, bsdf.pdf(wo, wi))
+        } else {
+            1.
+        };
+        color += contribution * weight;
+    }
+    color
+}
+
+// Light from all lights scattered towards wo at a point inside a medium,
+// weighted against sampling the phase function
+pub fn scattered_light(scene: &Scene, phase: &HenyeyGreenstein, point: Point, wo: Vector) -> Color {
+    let mut color = Color::BLACK;
+    for light in scene.light_sources() {
+        let (light_color, light_ray) = light.sample(scene, point);
+        let wi = -light_ray.direction;
+        let phase_pdf = phase.evaluate(wo, wi);
+        let light_pdf = light.pdf(point, wi);
+        let weight = if light_pdf > 0. && !seen_through(scene, point, light_ray) {
+            power_heuristic(light_pdf, phase_pdf)
+        } else {
+            1.
+        };
+        color += light_color * phase_pdf * weight;
+    }
+    color
+}
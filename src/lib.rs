@@ -1,14 +1,32 @@
-pub mod camera;
+// Constants are written for double precision
+#![cfg_attr(feature = "f32", allow(clippy::excessive_precision))]
+
+pub mod aov;
+pub mod bsdf;
 pub mod buffer;
+pub mod camera;
 pub mod color;
+pub mod environment;
+pub mod float;
 pub mod fractals;
 pub mod geometries;
 mod halton;
+pub mod ies;
+pub mod integrator;
 pub mod light;
+pub mod manylights;
 pub mod materials;
+pub mod medium;
+pub mod microfacet;
+pub mod noise;
 pub mod objects;
 pub mod point;
+pub mod principled;
 pub mod ray;
+pub mod sampling;
 pub mod scene;
 pub mod sdf;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
 pub mod vector;
@@ -1,28 +1,667 @@
+use crate::bsdf::Frame;
 use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::geometries::{point_bounds, Geometry};
+use crate::ies::IesProfile;
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sampling::{random2, uniform_cone};
 use crate::scene::Scene;
+use crate::spectrum::{illuminant, reflectance, Illuminant, Spectrum};
+use crate::vector::Vector;
 
+// Colors returned are at the current wavelengths when rendering spectrally
 pub trait Light: Sync {
     fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray);
+
+    // Distance to and radiance of lights with a surface hit by the ray
+    fn intersect(&self, _ray: Ray) -> Option<(Float, Color)> {
+        None
+    }
+
+    // Pdf with respect to solid angle of sample choosing the direction from the
+    // point, zero for lights that rays can't hit
+    fn pdf(&self, _point: Point, _direction: Vector) -> Float {
+        0.
+    }
+
+    // Where and in which directions the light emits, used to choose between
+    // many lights. None for lights at infinity.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+pub(crate) fn mean(color: Color) -> Float {
+    (color.red + color.green + color.blue) / 3.
+}
+
+// cos(max(0, a - b)) and sin(max(0, a - b)) from the sines and cosines
+fn cos_sub_clamped((sin_a, cos_a): (Float, Float), (sin_b, cos_b): (Float, Float)) -> Float {
+    if cos_a > cos_b {
+        1.
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+fn sin_sub_clamped((sin_a, cos_a): (Float, Float), (sin_b, cos_b): (Float, Float)) -> Float {
+    if cos_a > cos_b {
+        0.
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn sin_from_cos(cos: Float) -> Float {
+    (1. - cos * cos).max(0.).sqrt()
+}
+
+// Box around the emitters with a cone around their normals, emission reaches
+// theta_e further out, see Conty Estevez and Kulla 2018
+#[derive(Copy, Clone, Debug)]
+pub struct LightBounds {
+    pub min: Point,
+    pub max: Point,
+    pub axis: Vector,
+    pub cos_theta_o: Float,
+    pub cos_theta_e: Float,
+    pub power: Float,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn centroid(&self) -> Point {
+        self.min + 0.5 * (self.max - self.min)
+    }
+
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0. {
+            return *other;
+        } else if other.power == 0. {
+            return *self;
+        }
+
+        let (min, max) = point_bounds([self.min, self.max, other.min, other.max]).unwrap();
+        let (axis, cos_theta_o) = cone_union(
+            (self.axis, self.cos_theta_o),
+            (other.axis, other.cos_theta_o),
+        );
+        LightBounds {
+            min,
+            max,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            power: self.power + other.power,
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    // Conservative estimate of the light reaching the point
+    pub fn importance(&self, point: Point) -> Float {
+        if self.power == 0. {
+            return 0.;
+        }
+
+        let center = self.centroid();
+        let diagonal = (self.max - self.min).norm();
+        let to_point = point - center;
+        // Points inside or close to the box see it as large, not infinitely bright
+        let distance2 = to_point.norm_squared().max(diagonal / 2.);
+
+        let mut cos_w = to_point.normalize() * self.axis.normalize();
+        if self.two_sided {
+            cos_w = cos_w.abs();
+        }
+        if to_point.norm_squared() == 0. {
+            cos_w = 1.;
+        }
+        let w = (sin_from_cos(cos_w), cos_w);
+
+        // Angle subtended by the bounding sphere of the box
+        let inside = (0..3).all(|i| {
+            let (p, lo, hi) = match i {
+                0 => (point.x, self.min.x, self.max.x),
+                1 => (point.y, self.min.y, self.max.y),
+                _ => (point.z, self.min.z, self.max.z),
+            };
+            lo <= p && p <= hi
+        });
+        let radius2 = (diagonal / 2.).powi(2);
+        let cos_b = if inside || to_point.norm_squared() <= radius2 {
+            -1.
+        } else {
+            (1. - radius2 / to_point.norm_squared()).max(0.).sqrt()
+        };
+
+        // Smallest angle between the direction to the point and the emitters' normals
+        let o = (sin_from_cos(self.cos_theta_o), self.cos_theta_o);
+        let x = (sin_sub_clamped(w, o), cos_sub_clamped(w, o));
+        let cos_theta = cos_sub_clamped(x, (sin_from_cos(cos_b), cos_b));
+        if cos_theta <= self.cos_theta_e {
+            return 0.;
+        }
+        self.power * cos_theta / distance2
+    }
+}
+
+// Smallest cone containing both, given as axis and cosine of the half angle
+fn cone_union(a: (Vector, Float), b: (Vector, Float)) -> (Vector, Float) {
+    let (theta_a, theta_b) = (a.1.clamp(-1., 1.).acos(), b.1.clamp(-1., 1.).acos());
+    let theta_d = (a.0.normalize() * b.0.normalize()).clamp(-1., 1.).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.;
+    let rotation_axis = a.0.cross(b.0);
+    if theta_o >= PI || rotation_axis.norm_squared() == 0. {
+        return (a.0, -1.);
+    }
+
+    // Rotate a's axis towards b's, Rodrigues' formula
+    let k = rotation_axis.normalize();
+    let v = a.0.normalize();
+    let (sin, cos) = (theta_o - theta_a).sin_cos();
+    let axis = cos * v + sin * k.cross(v) + (1. - cos) * (k * v) * k;
+    (axis, theta_o.cos())
+}
+
+// Orients a measured distribution in the scene, scaling the light's color by
+// the relative intensity in each direction
+pub struct LightProfile {
+    ies: IesProfile,
+    max_candela: Float,
+    // Of the nadir, where the vertical angle is zero
+    pub direction: Vector,
+    // Where the horizontal angle is zero, made perpendicular to the direction
+    pub horizontal: Vector,
+}
+
+impl LightProfile {
+    pub fn new(ies: IesProfile, direction: Vector) -> LightProfile {
+        LightProfile {
+            max_candela: ies.max_candela(),
+            ies,
+            direction,
+            horizontal: direction.orthonormals().0,
+        }
+    }
+
+    pub fn ies(&self) -> &IesProfile {
+        &self.ies
+    }
+
+    // Peak intensity in candela, as measured or for lamps of another flux.
+    // Relative photometry scales with the rated flux of the lamps, absolute
+    // photometry with the flux of the whole distribution.
+    pub fn max_intensity(&self, lumens: Option<Float>) -> Float {
+        let Some(lumens) = lumens else {
+            return self.max_candela;
+        };
+        let reference = self.ies.lumens.unwrap_or_else(|| self.ies.flux());
+        if reference > 0. {
+            self.max_candela * lumens / reference
+        } else {
+            0.
+        }
+    }
+
+    pub fn scale(&self, direction: Vector) -> Float {
+        let n = self.direction.normalize();
+        let s = (self.horizontal - (self.horizontal * n) * n).normalize();
+        let local = Frame {
+            s,
+            t: n.cross(s),
+            n,
+        }
+        .to_local(direction.normalize());
+
+        let vertical = local.z.clamp(-1., 1.).acos().to_degrees();
+        let horizontal = local.y.atan2(local.x).to_degrees();
+        if self.max_candela > 0. {
+            self.ies.candela(vertical, horizontal) / self.max_candela
+        } else {
+            0.
+        }
+    }
+}
+
+// How the light of a PointLight decreases with the distance
+#[derive(Copy, Clone, Debug, Default)]
+pub enum Falloff {
+    // Physically correct
+    #[default]
+    InverseSquare,
+    Linear,
+    Constant,
+    // Inverse square smoothly reaching zero at the given distance
+    Windowed(Float),
 }
 
+impl Falloff {
+    pub fn attenuation(self, distance: Float) -> Float {
+        match self {
+            Falloff::InverseSquare => (distance * distance).recip(),
+            Falloff::Linear => distance.recip(),
+            Falloff::Constant => 1.,
+            Falloff::Windowed(cutoff) => {
+                let window = (1. - (distance / cutoff).powi(4)).clamp(0., 1.);
+                window * window / (distance * distance)
+            }
+        }
+    }
+}
+
+// Luminous flux, watts are radiant flux converted with the efficacy of the
+// spectrum. Colors of lights created from it are photometric: an intensity of 1
+// is 1 cd, a radiance of 1 is 1 cd/m².
+#[derive(Copy, Clone, Debug)]
+pub enum Power {
+    Lumens(Float),
+    Watts(Float),
+}
+
+impl Power {
+    pub fn lumens(self, illuminant: Illuminant) -> Float {
+        match self {
+            Power::Lumens(lumens) => lumens,
+            Power::Watts(watts) => watts * illuminant.efficacy(),
+        }
+    }
+}
+
+// A nonzero radius makes the light a sphere for the shadow test, giving soft
+// shadows, and keeps the falloff finite within it
 pub struct PointLight {
     pub center: Point,
     pub color: Color,
+    pub profile: Option<LightProfile>,
+    pub falloff: Falloff,
+    pub radius: Float,
+    // Of the color when rendering spectrally, otherwise upsampled with white as D65
+    pub spectrum: Option<Spectrum>,
+}
+
+impl PointLight {
+    // Emitting the flux uniformly in all directions
+    pub fn with_power(center: Point, illuminant: Illuminant, power: Power) -> PointLight {
+        PointLight {
+            center,
+            color: illuminant.color() * (power.lumens(illuminant) / (4. * PI)),
+            profile: None,
+            falloff: Falloff::InverseSquare,
+            radius: 0.,
+            spectrum: Some(Spectrum::new(illuminant)),
+        }
+    }
+
+    // With the intensities of the profile, see LightProfile::max_intensity
+    pub fn with_profile(
+        center: Point,
+        illuminant: Illuminant,
+        profile: LightProfile,
+        power: Option<Power>,
+    ) -> PointLight {
+        let intensity = profile.max_intensity(power.map(|power| power.lumens(illuminant)));
+        PointLight {
+            center,
+            color: illuminant.color() * intensity,
+            profile: Some(profile),
+            falloff: Falloff::InverseSquare,
+            radius: 0.,
+            spectrum: Some(Spectrum::new(illuminant)),
+        }
+    }
+
+    fn intensity(&self, direction: Vector) -> Color {
+        match &self.profile {
+            Some(profile) => emission(self.color, &self.spectrum) * profile.scale(direction),
+            None => emission(self.color, &self.spectrum),
+        }
+    }
 }
 
 impl Light for PointLight {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let to_center = self.center - point;
+        let distance = to_center.norm();
+        let origin = if self.radius > 0. && distance > self.radius {
+            let cos_max = (1. - (self.radius / distance).powi(2)).max(0.).sqrt();
+            let direction =
+                Frame::from_normal(to_center).to_world(uniform_cone(random2(), cos_max));
+            // Nearest intersection with the sphere
+            let b = direction * -to_center;
+            let t = -b
+                - (b * b - distance * distance + self.radius * self.radius)
+                    .max(0.)
+                    .sqrt();
+            point + t * direction
+        } else {
+            self.center
+        };
+        let to_obj = Ray {
+            origin,
+            direction: (point - origin).normalize(),
+        };
+
+        let attenuation = self.falloff.attenuation(distance.max(self.radius));
+        let visibility = reflectance(scene.see(point, origin));
+        (
+            self.intensity(-to_center.normalize()) * visibility * attenuation,
+            to_obj,
+        )
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = Vector::new(self.radius, self.radius, self.radius);
+        Some(LightBounds {
+            min: self.center - r,
+            max: self.center + r,
+            axis: Vector::K,
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+            power: 4. * PI * mean(self.color),
+            two_sided: false,
+        })
+    }
+}
+
+fn emission(color: Color, spectrum: &Option<Spectrum>) -> Color {
+    match spectrum {
+        Some(spectrum) => spectrum.emission(color),
+        None => illuminant(color),
+    }
+}
+
+// Distance at which lights infinitely far away are placed for the shadow test
+pub(crate) const FAR: Float = 1e6;
+
+// Parallel light like the sun, a nonzero angular diameter (in degrees) gives
+// soft shadows. The color is the irradiance on a perpendicular surface.
+pub struct DirectionalLight {
+    // Direction in which the light travels
+    pub direction: Vector,
+    pub color: Color,
+    pub angular_diameter: Float,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let direction = if self.angular_diameter > 0. {
+            let cos_max = (self.angular_diameter.to_radians() / 2.).cos();
+            Frame::from_normal(self.direction).to_world(uniform_cone(random2(), cos_max))
+        } else {
+            self.direction.normalize()
+        };
+
+        let origin = point - FAR * direction;
+        let to_obj = Ray { origin, direction };
+        (
+            illuminant(self.color) * reflectance(scene.see(point, origin)),
+            to_obj,
+        )
+    }
+}
+
+// Point light limited to a cone, fully lit within the inner angle and fading
+// out smoothly towards the outer angle. Both angles are in degrees from the axis.
+pub struct SpotLight {
+    pub center: Point,
+    pub direction: Vector,
+    pub color: Color,
+    pub inner_angle: Float,
+    pub outer_angle: Float,
+    // Applied on top of the cone
+    pub profile: Option<LightProfile>,
+    pub spectrum: Option<Spectrum>,
+}
+
+impl SpotLight {
+    // Emitting the flux within the cone, the smooth edge counting as half lit
+    pub fn with_power(
+        center: Point,
+        direction: Vector,
+        inner_angle: Float,
+        outer_angle: Float,
+        illuminant: Illuminant,
+        power: Power,
+    ) -> SpotLight {
+        let cos_edge = (inner_angle.to_radians().cos() + outer_angle.to_radians().cos()) / 2.;
+        let solid_angle = 2. * PI * (1. - cos_edge);
+        SpotLight {
+            center,
+            direction,
+            color: illuminant.color() * (power.lumens(illuminant) / solid_angle),
+            inner_angle,
+            outer_angle,
+            profile: None,
+            spectrum: Some(Spectrum::new(illuminant)),
+        }
+    }
+
+    // With the intensities of the profile within the cone, see
+    // LightProfile::max_intensity
+    pub fn with_profile(
+        center: Point,
+        direction: Vector,
+        inner_angle: Float,
+        outer_angle: Float,
+        illuminant: Illuminant,
+        profile: LightProfile,
+        power: Option<Power>,
+    ) -> SpotLight {
+        let intensity = profile.max_intensity(power.map(|power| power.lumens(illuminant)));
+        SpotLight {
+            center,
+            direction,
+            color: illuminant.color() * intensity,
+            inner_angle,
+            outer_angle,
+            profile: Some(profile),
+            spectrum: Some(Spectrum::new(illuminant)),
+        }
+    }
+
+    fn falloff(&self, direction: Vector) -> Float {
+        let scale = self
+            .profile
+            .as_ref()
+            .map_or(1., |profile| profile.scale(direction));
+        scale * self.cone(direction)
+    }
+
+    fn cone(&self, direction: Vector) -> Float {
+        let cos = direction * self.direction.normalize();
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        if cos >= cos_inner {
+            1.
+        } else if cos <= cos_outer {
+            0.
+        } else {
+            let t = (cos - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3. - 2. * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
     fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
         let to_obj = Ray {
             origin: self.center,
             direction: (point - self.center).normalize(),
         };
 
-        if scene.see(point, self.center) {
-            (self.color * (point - self.center).norm_squared().recip(), to_obj)
+        let falloff = self.falloff(to_obj.direction);
+        if falloff > 0. {
+            let visibility = reflectance(scene.see(point, self.center));
+            (
+                emission(self.color, &self.spectrum)
+                    * visibility
+                    * (falloff * (point - self.center).norm_squared().recip()),
+                to_obj,
+            )
         } else {
             (Color::BLACK, to_obj)
         }
     }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (inner, outer) = (self.inner_angle.to_radians(), self.outer_angle.to_radians());
+        Some(LightBounds {
+            min: self.center,
+            max: self.center,
+            axis: self.direction.normalize(),
+            cos_theta_o: inner.cos(),
+            cos_theta_e: (outer - inner).max(0.).cos(),
+            power: 4. * PI * mean(self.color),
+            two_sided: false,
+        })
+    }
+}
+
+// Light emitted uniformly from the surface of a geometry, sampled over its
+// area for soft shadows. The color is the emitted radiance.
+pub struct AreaLight {
+    pub geometry: Box<dyn Geometry>,
+    pub color: Color,
+    // Otherwise only the side the normal points to emits
+    pub two_sided: bool,
+    pub spectrum: Option<Spectrum>,
+}
+
+impl AreaLight {
+    // Lambertian emitter of the flux, the geometry must have a finite area
+    pub fn with_power(
+        geometry: Box<dyn Geometry>,
+        illuminant: Illuminant,
+        power: Power,
+        two_sided: bool,
+    ) -> AreaLight {
+        let sides = if two_sided { 2. } else { 1. };
+        let radiance = power.lumens(illuminant) / (PI * geometry.area() * sides);
+        AreaLight {
+            geometry,
+            color: illuminant.color() * radiance,
+            two_sided,
+            spectrum: Some(Spectrum::new(illuminant)),
+        }
+    }
+
+    fn emits_towards(&self, normal: Vector, direction: Vector) -> bool {
+        self.two_sided || normal * direction > 0.
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let sample = self.geometry.sample(point, random2());
+        let (light_point, normal, pdf) = match sample {
+            Some(sample) if sample.pdf > 0. => (sample.point, sample.normal, sample.pdf),
+            _ => {
+                let to_obj = Ray {
+                    origin: point,
+                    direction: Vector::K,
+                };
+                return (Color::BLACK, to_obj);
+            }
+        };
+
+        let to_obj = Ray {
+            origin: light_point,
+            direction: (point - light_point).normalize(),
+        };
+        if self.emits_towards(normal, to_obj.direction) {
+            (
+                emission(self.color, &self.spectrum)
+                    * reflectance(scene.see_surface(point, light_point))
+                    * pdf.recip(),
+                to_obj,
+            )
+        } else {
+            (Color::BLACK, to_obj)
+        }
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<(Float, Color)> {
+        let (distance, part) = self.geometry.intersect_part(ray)?;
+        let normal = self
+            .geometry
+            .part_hit(ray.origin + distance * ray.direction, part)
+            .normal;
+        if self.emits_towards(normal, -ray.direction) {
+            Some((distance, emission(self.color, &self.spectrum)))
+        } else {
+            Some((distance, Color::BLACK))
+        }
+    }
+
+    fn pdf(&self, point: Point, direction: Vector) -> Float {
+        self.geometry.pdf(point, direction)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (min, max) = self.geometry.bounds()?;
+        let (axis, cos_theta_o) = self.geometry.normal_cone();
+        let sides = if self.two_sided { 2. } else { 1. };
+        Some(LightBounds {
+            min,
+            max,
+            axis,
+            cos_theta_o,
+            cos_theta_e: 0.,
+            power: sides * PI * mean(self.color) * self.geometry.area(),
+            two_sided: self.two_sided,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_intensity() {
+        let ies = "TILT=NONE\n1 1000 2 2 1 1 1 0 0 0\n1 1 0\n0 180\n0\n100 50\n";
+        let relative = IesProfile::read(&mut ies.as_bytes()).unwrap();
+        let absolute = IesProfile::read(&mut ies.replace("1000", "-1").as_bytes()).unwrap();
+        let flux = absolute.flux();
+
+        let relative = LightProfile::new(relative, -Vector::K);
+        assert_eq!(relative.max_intensity(None), 200.);
+        assert_eq!(relative.max_intensity(Some(500.)), 100.);
+        let absolute = LightProfile::new(absolute, -Vector::K);
+        assert_eq!(absolute.max_intensity(None), 200.);
+        assert!((absolute.max_intensity(Some(flux / 2.)) - 100.).abs() < 1e-3);
+
+        let light = PointLight::with_profile(
+            Point::ORIGIN,
+            Illuminant::D65,
+            relative,
+            Some(Power::Lumens(2000.)),
+        );
+        let (down, up) = (light.intensity(-Vector::K), light.intensity(Vector::K));
+        assert!((down.green / up.green - 2.).abs() < 1e-3);
+        assert!((light.color.green / Illuminant::D65.color().green - 400.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn falloff_attenuation() {
+        assert_eq!(Falloff::InverseSquare.attenuation(2.), 0.25);
+        assert_eq!(Falloff::Linear.attenuation(2.), 0.5);
+        assert_eq!(Falloff::Constant.attenuation(2.), 1.);
+
+        // Close to the inverse square near the light, zero from the cutoff on
+        let windowed = Falloff::Windowed(10.);
+        assert!((windowed.attenuation(0.5) - 4.).abs() < 1e-3);
+        assert!(windowed.attenuation(5.) < 0.04 && windowed.attenuation(5.) > 0.);
+        assert_eq!(windowed.attenuation(10.), 0.);
+        assert_eq!(windowed.attenuation(20.), 0.);
+    }
 }
\ No newline at end of file
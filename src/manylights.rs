@@ -0,0 +1,342 @@
+use crate::color::Color;
+use crate::float::Float;
+use crate::geometries::box_interval;
+use crate::light::{Light, LightBounds};
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampling::{random, Distribution1D};
+use crate::scene::Scene;
+use crate::vector::Vector;
+
+// Lights that choose one of many lights per sample, so shading costs a single
+// shadow ray however many lights they contain. Add them to Scene::lights in
+// place of the lights themselves.
+
+// Nearest of the lights hit by the ray
+fn nearest<'a>(
+    lights: impl Iterator<Item = (usize, &'a Box<dyn Light>)>,
+    ray: Ray,
+) -> Option<(Float, Color, usize)> {
+    lights
+        .filter_map(|(i, light)| {
+            light
+                .intersect(ray)
+                .map(|(distance, radiance)| (distance, radiance, i))
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+}
+
+fn union(bounds: impl Iterator<Item = Option<LightBounds>>) -> Option<LightBounds> {
+    bounds.reduce(|a, b| Some(a?.union(&b?))).flatten()
+}
+
+fn sample_chosen(light: &dyn Light, pmf: Float, scene: &Scene, point: Point) -> (Color, Ray) {
+    let (color, ray) = light.sample(scene, point);
+    (color * pmf.recip(), ray)
+}
+
+fn no_sample(point: Point) -> (Color, Ray) {
+    let ray = Ray {
+        origin: point,
+        direction: Vector::K,
+    };
+    (Color::BLACK, ray)
+}
+
+// Chooses lights in proportion to their power regardless of where they are,
+// lights at infinity as often as the average light with bounds
+pub struct PowerLights {
+    lights: Vec<Box<dyn Light>>,
+    distribution: Distribution1D,
+}
+
+impl PowerLights {
+    pub fn new(lights: Vec<Box<dyn Light>>) -> PowerLights {
+        let powers: Vec<_> = lights
+            .iter()
+            .map(|light| light.bounds().map(|bounds| bounds.power))
+            .collect();
+        let bounded: Vec<_> = powers.iter().flatten().collect();
+        let average = if bounded.is_empty() {
+            1.
+        } else {
+            bounded.iter().copied().sum::<Float>() / bounded.len() as Float
+        };
+        let weights: Vec<_> = powers.iter().map(|p| p.unwrap_or(average)).collect();
+
+        PowerLights {
+            lights,
+            distribution: Distribution1D::new(&weights),
+        }
+    }
+}
+
+impl Light for PowerLights {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        if self.lights.is_empty() {
+            return no_sample(point);
+        }
+        let (i, pmf) = self.distribution.sample_discrete(random());
+        sample_chosen(self.lights[i].as_ref(), pmf, scene, point)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<(Float, Color)> {
+        nearest(self.lights.iter().enumerate(), ray).map(|(d, radiance, _)| (d, radiance))
+    }
+
+    fn pdf(&self, point: Point, direction: Vector) -> Float {
+        let ray = Ray {
+            origin: point,
+            direction: direction.normalize(),
+        };
+        match nearest(self.lights.iter().enumerate(), ray) {
+            Some((_, _, i)) => self.distribution.pmf(i) * self.lights[i].pdf(point, direction),
+            None => 0.,
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        union(self.lights.iter().map(|light| light.bounds()))
+    }
+}
+
+enum Node {
+    Leaf(LightBounds, usize),
+    // The first child directly follows its parent
+    Interior(LightBounds, usize),
+}
+
+impl Node {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            Node::Leaf(bounds, _) | Node::Interior(bounds, _) => bounds,
+        }
+    }
+}
+
+// Hierarchy over the lights' bounds, sampling descends towards the children
+// that are estimated to contribute most to the shaded point, see Conty Estevez
+// and Kulla 2018. Lights at infinity are chosen uniformly next to the tree.
+pub struct LightBVH {
+    lights: Vec<Box<dyn Light>>,
+    nodes: Vec<Node>,
+    infinite: Vec<usize>,
+    // Of each light, whether it is one of the infinite ones
+    at_infinity: Vec<bool>,
+    // Choices from the root to the leaf of each light, one bit per level
+    trails: Vec<u64>,
+}
+
+impl LightBVH {
+    pub fn new(lights: Vec<Box<dyn Light>>) -> LightBVH {
+        let mut bvh = LightBVH {
+            nodes: Vec::new(),
+            infinite: Vec::new(),
+            at_infinity: vec![false; lights.len()],
+            trails: vec![0; lights.len()],
+            lights: Vec::new(),
+        };
+
+        let mut bounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0. => bounded.push((i, bounds)),
+                Some(_) => {}
+                None => {
+                    bvh.infinite.push(i);
+                    bvh.at_infinity[i] = true;
+                }
+            }
+        }
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded, 0, 0);
+        }
+        bvh.lights = lights;
+        bvh
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], depth: usize, trail: u64) {
+        // Median splits keep the depth well below the bits in a trail
+        if lights.len() == 1 {
+            let (i, bounds) = lights[0];
+            self.trails[i] = trail;
+            self.nodes.push(Node::Leaf(bounds, i));
+            return;
+        }
+
+        // Median split along the longest axis of the centroids
+        let bounds = lights
+            .iter()
+            .map(|(_, bounds)| *bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let centroids: Vec<_> = lights.iter().map(|(_, b)| b.centroid()).collect();
+        let lo = centroids.iter().fold(centroids[0], |a, c| {
+            Point::new(a.x.min(c.x), a.y.min(c.y), a.z.min(c.z))
+        });
+        let hi = centroids.iter().fold(centroids[0], |a, c| {
+            Point::new(a.x.max(c.x), a.y.max(c.y), a.z.max(c.z))
+        });
+        let extent = hi - lo;
+        let key = |p: Point| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                p.x
+            } else if extent.y >= extent.z {
+                p.y
+            } else {
+                p.z
+            }
+        };
+        lights.sort_by(|a, b| key(a.1.centroid()).total_cmp(&key(b.1.centroid())));
+
+        let node = self.nodes.len();
+        self.nodes.push(Node::Interior(bounds, 0));
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        self.build(left, depth + 1, trail);
+        let second = self.nodes.len();
+        self.build(right, depth + 1, trail | (1 << depth));
+        self.nodes[node] = Node::Interior(bounds, second);
+    }
+
+    fn infinite_probability(&self) -> Float {
+        let trees = if self.nodes.is_empty() { 0 } else { 1 };
+        self.infinite.len() as Float / (self.infinite.len() + trees) as Float
+    }
+
+    // Nearest light hit, skipping subtrees whose boxes the ray misses
+    fn nearest(&self, ray: Ray) -> Option<(Float, Color, usize)> {
+        let mut best = nearest(self.infinite.iter().map(|&i| (i, &self.lights[i])), ray);
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let distance = best.map_or(Float::INFINITY, |(d, _, _)| d);
+            let bounds = self.nodes[node].bounds();
+            if box_interval(bounds.min, bounds.max, ray, distance).is_none() {
+                continue;
+            }
+            match self.nodes[node] {
+                Node::Leaf(_, i) => {
+                    if let Some((d, radiance)) = self.lights[i].intersect(ray) {
+                        if d < distance {
+                            best = Some((d, radiance, i));
+                        }
+                    }
+                }
+                Node::Interior(_, second) => {
+                    stack.push(second);
+                    stack.push(node + 1);
+                }
+            }
+        }
+        best
+    }
+
+    // Probability of choosing the light for the point
+    fn pmf(&self, point: Point, light: usize) -> Float {
+        let p_infinite = self.infinite_probability();
+        if self.at_infinity[light] {
+            return p_infinite / self.infinite.len() as Float;
+        }
+        if self.nodes.is_empty() {
+            return 0.;
+        }
+
+        let (mut node, mut pmf, mut trail) = (0, 1. - p_infinite, self.trails[light]);
+        loop {
+            match self.nodes[node] {
+                Node::Leaf(bounds, i) => {
+                    let reached = i == light && bounds.importance(point) > 0.;
+                    return if reached { pmf } else { 0. };
+                }
+                Node::Interior(_, second) => {
+                    let importance = [
+                        self.nodes[node + 1].bounds().importance(point),
+                        self.nodes[second].bounds().importance(point),
+                    ];
+                    let total = importance[0] + importance[1];
+                    let child = (trail & 1) as usize;
+                    if total == 0. {
+                        return 0.;
+                    }
+                    pmf *= importance[child] / total;
+                    node = if child == 0 { node + 1 } else { second };
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+impl Light for LightBVH {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let p_infinite = self.infinite_probability();
+        let mut u = random();
+        if u < p_infinite {
+            let n = self.infinite.len();
+            let i = ((u / p_infinite * n as Float) as usize).min(n - 1);
+            let light = self.lights[self.infinite[i]].as_ref();
+            return sample_chosen(light, p_infinite / n as Float, scene, point);
+        }
+        if self.nodes.is_empty() {
+            return no_sample(point);
+        }
+
+        // Reuse the remainder of u at every level
+        u = (u - p_infinite) / (1. - p_infinite);
+        let (mut node, mut pmf) = (0, 1. - p_infinite);
+        loop {
+            match self.nodes[node] {
+                Node::Leaf(bounds, i) => {
+                    if bounds.importance(point) == 0. {
+                        return no_sample(point);
+                    }
+                    return sample_chosen(self.lights[i].as_ref(), pmf, scene, point);
+                }
+                Node::Interior(_, second) => {
+                    let importance = [
+                        self.nodes[node + 1].bounds().importance(point),
+                        self.nodes[second].bounds().importance(point),
+                    ];
+                    let total = importance[0] + importance[1];
+                    if total == 0. {
+                        return no_sample(point);
+                    }
+                    let p = importance[0] / total;
+                    if u < p {
+                        node += 1;
+                        pmf *= p;
+                        u = (u / p).min(1. - Float::EPSILON);
+                    } else {
+                        node = second;
+                        pmf *= 1. - p;
+                        u = ((u - p) / (1. - p)).min(1. - Float::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<(Float, Color)> {
+        self.nearest(ray).map(|(d, radiance, _)| (d, radiance))
+    }
+
+    fn pdf(&self, point: Point, direction: Vector) -> Float {
+        let ray = Ray {
+            origin: point,
+            direction: direction.normalize(),
+        };
+        match self.nearest(ray) {
+            Some((_, _, i)) => self.pmf(point, i) * self.lights[i].pdf(point, direction),
+            None => 0.,
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        if !self.infinite.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| *node.bounds())
+    }
+}
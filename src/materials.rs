@@ -1,60 +1,214 @@
+use std::sync::Arc;
+
+use crate::bsdf::{
+    fresnel_conductor, fresnel_dielectric, reflect, refract, Frame, LambertianBSDF, MixBSDF,
+    SpecularDielectric, SpecularReflection, BSDF,
+};
+use crate::camera;
 use crate::color::Color;
+use crate::float::Float;
+use crate::geometries::Hit;
+use crate::integrator::{direct_lighting, offset, sample_light};
+use crate::light::Light;
+use crate::medium::{HomogeneousMedium, Medium};
+use crate::microfacet::{ConductorBSDF, RoughDielectricBSDF, TrowbridgeReitz};
 use crate::point::Point;
+use crate::principled::PrincipledBSDF;
 use crate::ray::Ray;
+use crate::sampling::random2;
 use crate::scene::Scene;
+use crate::spectrum;
+use crate::texture::{spherical_tangents, spherical_uv, Texture};
 use crate::vector::Vector;
 
-pub trait Material: Sync {
-    fn surface_color(
+pub trait Material: Send + Sync {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color;
+
+    // Used by the AOV passes, defaults to a material without a diffuse component
+    fn albedo(&self, _hit: &Hit) -> Color {
+        Color::BLACK
+    }
+
+    // Direct light received from a single light, see AOV::Light
+    fn light_contribution(
+        &self,
+        _scene: &Scene,
+        _light: &dyn Light,
+        _hit: &Hit,
+        _ray: Ray,
+    ) -> Color {
+        Color::BLACK
+    }
+
+    // Used by the path tracer, materials without a BSDF end the path
+    fn bsdf(&self, _hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        None
+    }
+
+    fn emitted(&self, _hit: &Hit, _ray: Ray) -> Color {
+        Color::BLACK
+    }
+
+    // Objects with emissive materials are sampled as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // Hit with the normal used for shading, see NormalMap and BumpMap
+    fn shading(&self, hit: &Hit) -> Hit {
+        *hit
+    }
+
+    // Fraction of light surviving a path of the given length inside the object,
+    // leaving it at the hit
+    fn interior_transmittance(&self, _hit: &Hit, _distance: Float) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    // Light passing straight through the surface in the direction, lets shadow
+    // rays through transparent objects. Refracting surfaces only let them through
+    // when the bending may be ignored, see Integrator::ignores_refraction.
+    // Opaque by default.
+    fn shadow_transmittance(
         &self,
-        scene: &Scene,
-        point: Point,
-        normal: Vector,
-        ray: Ray,
-        bounces: u32,
-    ) -> Color;
+        _hit: &Hit,
+        _direction: Vector,
+        _ignore_refraction: bool,
+    ) -> Color {
+        Color::BLACK
+    }
+
+    // Fills the interior of objects without a medium of their own, looked up
+    // where rays leave the object, see Subsurface
+    fn medium(&self, _hit: &Hit) -> Option<Box<dyn Medium + '_>> {
+        Option::None
+    }
+
+    // Identifies the material in AOV::MaterialId, materials shared through an
+    // Arc have the same one
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+// Lets objects share a single material
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        self.as_ref().surface_color(scene, hit, ray, bounces)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.as_ref().albedo(hit)
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        self.as_ref().light_contribution(scene, light, hit, ray)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        self.as_ref().bsdf(hit)
+    }
+
+    fn emitted(&self, hit: &Hit, ray: Ray) -> Color {
+        self.as_ref().emitted(hit, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.as_ref().is_emissive()
+    }
+
+    fn shading(&self, hit: &Hit) -> Hit {
+        self.as_ref().shading(hit)
+    }
+
+    fn interior_transmittance(&self, hit: &Hit, distance: Float) -> Color {
+        self.as_ref().interior_transmittance(hit, distance)
+    }
+
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        self.as_ref()
+            .shadow_transmittance(hit, direction, ignore_refraction)
+    }
+
+    fn medium(&self, hit: &Hit) -> Option<Box<dyn Medium + '_>> {
+        self.as_ref().medium(hit)
+    }
+
+    fn id(&self) -> usize {
+        self.as_ref().id()
+    }
 }
 
 pub trait Background: Sync {
     fn background_color(&self, scene: &Scene, ray: Ray) -> Color;
+
+    // Backgrounds that can be sampled light the scene like other lights
+    fn light(&self) -> Option<&dyn Light> {
+        Option::None
+    }
 }
 
-const BIAS: f64 = 32. * f64::EPSILON;
+const BIAS: Float = 32. * Float::EPSILON;
+
+// Follows a single ray sampled from the BSDF, for glossy materials in the recursive renderer
+fn sampled_reflection(scene: &Scene, bsdf: &dyn BSDF, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+    match bsdf.sample(-ray.direction, random2()) {
+        Some(sample) if sample.pdf > 0. => {
+            let sampled_ray = Ray {
+                origin: offset(hit.point, hit.normal, sample.direction),
+                direction: sample.direction.normalize(),
+            };
+            let pdf = (!sample.specular).then_some(sample.pdf);
+            sample.weight(hit.normal) * scene.sampled_color(sampled_ray, bounces + 1, pdf)
+        }
+        _ => Color::BLACK,
+    }
+}
 
 pub struct DiffuseEmitter {
-    pub color: Color,
+    pub color: Box<dyn Texture>,
 }
 
 impl Material for DiffuseEmitter {
-    fn surface_color(
-        &self,
-        _scene: &Scene,
-        _point: Point,
-        _normal: Vector,
-        _ray: Ray,
-        _bounces: u32,
-    ) -> Color {
-        self.color
+    fn surface_color(&self, _scene: &Scene, hit: &Hit, _ray: Ray, _bounces: u32) -> Color {
+        self.color.color(hit)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.color.color(hit).clamp()
+    }
+
+    fn emitted(&self, hit: &Hit, _ray: Ray) -> Color {
+        self.color.color(hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
     }
 }
 
+// As a background the texture is looked up on the unit sphere of directions
 impl Background for DiffuseEmitter {
-    fn background_color(&self, _scene: &Scene, _ray: Ray) -> Color {
-        self.color
+    fn background_color(&self, _scene: &Scene, ray: Ray) -> Color {
+        let (dpdu, dpdv) = spherical_tangents(ray.direction);
+        let direction = ray.direction.normalize();
+        let hit = Hit {
+            point: Point::ORIGIN + direction,
+            normal: -direction,
+            uv: spherical_uv(direction),
+            dpdu,
+            dpdv,
+            footprint: camera::pixel_spread(),
+        };
+        self.color.color(&hit)
     }
 }
 
 pub struct SurfaceNormal {}
 
 impl Material for SurfaceNormal {
-    fn surface_color(
-        &self,
-        _scene: &Scene,
-        _point: Point,
-        normal: Vector,
-        _ray: Ray,
-        _bounces: u32,
-    ) -> Color {
+    fn surface_color(&self, _scene: &Scene, hit: &Hit, _ray: Ray, _bounces: u32) -> Color {
+        let normal = hit.normal;
         Color::new((normal.x + 1.) / 2., (normal.y + 1.) / 2., normal.z).powf(2.2)
     }
 }
@@ -62,15 +216,8 @@ impl Material for SurfaceNormal {
 pub struct DepthMap {}
 
 impl Material for DepthMap {
-    fn surface_color(
-        &self,
-        _scene: &Scene,
-        point: Point,
-        _normal: Vector,
-        _ray: Ray,
-        _bounces: u32,
-    ) -> Color {
-        let gray = point.z.rem_euclid(1.) / 1.5 + 0.1;
+    fn surface_color(&self, _scene: &Scene, hit: &Hit, _ray: Ray, _bounces: u32) -> Color {
+        let gray = hit.point.z.rem_euclid(1.) / 1.5 + 0.1;
         Color::new(gray, gray, gray).powf(2.2)
     }
 }
@@ -78,14 +225,7 @@ impl Material for DepthMap {
 pub struct None {}
 
 impl Material for None {
-    fn surface_color(
-        &self,
-        _scene: &Scene,
-        _point: Point,
-        _normal: Vector,
-        _ray: Ray,
-        _bounces: u32,
-    ) -> Color {
+    fn surface_color(&self, _scene: &Scene, _hit: &Hit, _ray: Ray, _bounces: u32) -> Color {
         Color::BLACK
     }
 }
@@ -97,82 +237,982 @@ impl Background for None {
 }
 
 pub struct PhongMaterial {
-    pub ambient_color: Color,
-    pub diffuse_color: Color,
-    pub specular_color: Color,
-    pub specular_power: f64,
+    pub ambient_color: Box<dyn Texture>,
+    pub diffuse_color: Box<dyn Texture>,
+    pub specular_color: Box<dyn Texture>,
+    pub specular_power: Box<dyn Texture>,
 }
 
 impl Material for PhongMaterial {
-    fn surface_color(
-        &self,
-        scene: &Scene,
-        point: Point,
-        normal: Vector,
-        ray: Ray,
-        _bounces: u32,
-    ) -> Color {
-        let mut color = self.ambient_color;
-
-        for light in &scene.lights {
-            let (light_color, light_ray) = light.sample(scene, point + normal * BIAS);
-
-            // Diffuse
-            let light_power = (normal * -light_ray.direction).max(0.0);
-            let light_color = light_color * light_power;
-            color = color + self.diffuse_color * light_color;
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, _bounces: u32) -> Color {
+        let mut color = self.ambient_color.color(hit);
 
-            // Specular
-            let h = (-light_ray.direction - ray.direction).normalize();
-            let specular_light_power = (h * normal).max(0.).powf(self.specular_power);
-            let specular_light_color = light_color * specular_light_power;
-            color = color + self.specular_color * specular_light_color;
+        for light in scene.light_sources() {
+            color += self.light_contribution(scene, light, hit, ray);
         }
         color
     }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.diffuse_color.color(hit)
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        let normal = hit.normal;
+        let (light_color, light_ray) = light.sample(scene, hit.point + normal * BIAS);
+
+        // Diffuse
+        let light_power = (normal * -light_ray.direction).max(0.0);
+        let light_color = light_color * light_power;
+        let diffuse = self.diffuse_color.color(hit) * light_color;
+
+        // Specular
+        let h = (-light_ray.direction - ray.direction).normalize();
+        let specular_light_power = (h * normal).max(0.).powf(self.specular_power.value(hit));
+        let specular_light_color = light_color * specular_light_power;
+        diffuse + self.specular_color.color(hit) * specular_light_color
+    }
 }
 
 pub struct Mirror {}
 
 impl Material for Mirror {
-    fn surface_color(
-        &self,
-        scene: &Scene,
-        point: Point,
-        normal: Vector,
-        ray: Ray,
-        bounces: u32,
-    ) -> Color {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        let normal = hit.normal;
         let reflection_ray = Ray {
-            origin: point + BIAS * normal,
+            origin: hit.point + BIAS * normal,
             direction: (ray.direction - 2. * (ray.direction * normal) * normal).normalize(),
         };
 
         scene.color(reflection_ray, bounces + 1)
     }
+
+    fn albedo(&self, _hit: &Hit) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        Some(Box::new(SpecularReflection {
+            normal: hit.normal,
+            reflectance: Color::new(1., 1., 1.),
+        }))
+    }
 }
 
-pub struct MixedMaterial<'a> {
-    material1: &'a dyn Material,
-    material2: &'a dyn Material,
-    mix: f64,
+// Glass-like interface, the geometry's normals must point outwards
+pub struct Dielectric {
+    pub ior: Box<dyn Texture>,
+    // Beer-Lambert absorption coefficient per unit length, looked up where
+    // rays leave the object
+    pub absorption: Box<dyn Texture>,
+    // Zero for a smooth surface
+    pub roughness: Box<dyn Texture>,
+    // Cauchy's B coefficient in µm², the ior is the one at 587.6nm. Only
+    // disperses light when rendering spectrally.
+    pub dispersion: Box<dyn Texture>,
 }
 
-impl Material for MixedMaterial<'_> {
-    fn surface_color(
-        &self,
-        scene: &Scene,
-        point: Point,
-        normal: Vector,
-        ray: Ray,
-        bounces: u32,
-    ) -> Color {
-        let color1 = self
-            .material1
-            .surface_color(scene, point, normal, ray, bounces);
-        let color2 = self
-            .material2
-            .surface_color(scene, point, normal, ray, bounces);
-        (1. - self.mix) * color1 + self.mix * color2
+impl Dielectric {
+    fn distribution(&self, hit: &Hit) -> TrowbridgeReitz {
+        let roughness = self.roughness.value(hit);
+        TrowbridgeReitz::from_roughness(roughness, roughness)
+    }
+
+    // At the hero wavelength when the ior depends on it, the other wavelengths
+    // would refract differently and are dropped
+    fn dispersed_ior(&self, hit: &Hit) -> Option<Float> {
+        let dispersion = self.dispersion.value(hit);
+        if dispersion == 0. {
+            return Option::None;
+        }
+        let lambda = spectrum::wavelengths()?.hero() / 1000.;
+        spectrum::terminate_secondary();
+        let ior = self.ior.value(hit);
+        Some(ior + dispersion * (lambda.powi(-2) - Float::powi(0.5876, -2)))
+    }
+
+    fn interface(&self, hit: &Hit, ior: Float) -> Box<dyn BSDF + '_> {
+        let distribution = self.distribution(hit);
+        if distribution.is_smooth() {
+            Box::new(SpecularDielectric {
+                normal: hit.normal,
+                ior,
+            })
+        } else {
+            Box::new(RoughDielectricBSDF {
+                frame: Frame::from_normal(hit.normal),
+                distribution,
+                ior,
+            })
+        }
+    }
+}
+
+impl Material for Dielectric {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        let wo = -ray.direction;
+        let ior = self.ior.value(hit);
+        let (outside, eta) = if wo * hit.normal >= 0. {
+            (hit.normal, ior)
+        } else {
+            (-hit.normal, ior.recip())
+        };
+
+        // Rough surfaces refract through a single sampled microfacet
+        let distribution = self.distribution(hit);
+        let facing = if distribution.is_smooth() {
+            outside
+        } else {
+            let frame = Frame::from_normal(outside);
+            frame.to_world(distribution.sample_wm(frame.to_local(wo), random2()))
+        };
+
+        let reflectance = fresnel_dielectric(wo * facing, eta);
+        let reflection_ray = Ray {
+            origin: hit.point + BIAS * outside,
+            direction: reflect(wo, facing).normalize(),
+        };
+        let reflected = scene.color(reflection_ray, bounces + 1);
+
+        match refract(wo, facing, eta) {
+            Some(direction) => {
+                let refraction_ray = Ray {
+                    origin: hit.point - BIAS * outside,
+                    direction: direction.normalize(),
+                };
+                let refracted = scene.color(refraction_ray, bounces + 1);
+                reflectance * reflected + (1. - reflectance) * refracted
+            }
+            // Total internal reflection
+            None => reflected,
+        }
+    }
+
+    fn albedo(&self, _hit: &Hit) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        let ior = self
+            .dispersed_ior(hit)
+            .unwrap_or_else(|| self.ior.value(hit));
+        Some(self.interface(hit, ior))
+    }
+
+    fn interior_transmittance(&self, hit: &Hit, distance: Float) -> Color {
+        let absorption = self.absorption.color(hit);
+        Color::new(
+            (-absorption.red * distance).exp(),
+            (-absorption.green * distance).exp(),
+            (-absorption.blue * distance).exp(),
+        )
+    }
+
+    // Rough surfaces scatter the rays and are opaque
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        let ior = self.ior.value(hit);
+        let refracts = ior != 1. || self.dispersion.value(hit) != 0.;
+        if !self.distribution(hit).is_smooth() || (refracts && !ignore_refraction) {
+            return Color::BLACK;
+        }
+        let cos = direction.normalize() * hit.normal;
+        let eta = if cos < 0. { ior } else { ior.recip() };
+        (1. - fresnel_dielectric(cos.abs(), eta)) * Color::new(1., 1., 1.)
+    }
+}
+
+// Random walk subsurface scattering through a medium behind a smooth dielectric
+// surface. Every scattering event counts as a bounce, so translucent objects
+// need a high maximum depth.
+pub struct Subsurface {
+    color: Box<dyn Texture>,
+    mean_free_path: Box<dyn Texture>,
+    surface: Dielectric,
+}
+
+// Keeps a zero mean free path from stopping the random walk in place
+const MIN_MEAN_FREE_PATH: Float = 1e-6;
+
+impl Subsurface {
+    // The color is the albedo after multiple scattering, the mean free path the
+    // average distance between scattering events in world units. Both are
+    // looked up where rays leave the object, the mean free path is clamped to
+    // a small positive length.
+    pub fn new(
+        color: Box<dyn Texture>,
+        mean_free_path: Box<dyn Texture>,
+        ior: Box<dyn Texture>,
+    ) -> Subsurface {
+        Subsurface {
+            color,
+            mean_free_path,
+            surface: Dielectric {
+                ior,
+                absorption: Box::new(Color::BLACK),
+                roughness: Box::new(0.),
+                dispersion: Box::new(0.),
+            },
+        }
+    }
+
+    fn interior(&self, hit: &Hit) -> HomogeneousMedium {
+        // Single scattering albedo, fitted by Chiang et al. 2016
+        let albedo = |a: Float| {
+            let a = a.clamp(0., 1.);
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            (1. - s * s).clamp(0., 1.)
+        };
+        let color = self.color.color(hit);
+        let mean_free_path = self.mean_free_path.color(hit);
+        let extinction = Color::new(
+            mean_free_path.red.max(MIN_MEAN_FREE_PATH).recip(),
+            mean_free_path.green.max(MIN_MEAN_FREE_PATH).recip(),
+            mean_free_path.blue.max(MIN_MEAN_FREE_PATH).recip(),
+        );
+        let scattering =
+            extinction * Color::new(albedo(color.red), albedo(color.green), albedo(color.blue));
+
+        HomogeneousMedium {
+            absorption: extinction - scattering,
+            scattering,
+            g: 0.,
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        self.surface.surface_color(scene, hit, ray, bounces)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.color.color(hit)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        self.surface.bsdf(hit)
+    }
+
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        self.surface
+            .shadow_transmittance(hit, direction, ignore_refraction)
+    }
+
+    fn medium(&self, hit: &Hit) -> Option<Box<dyn Medium + '_>> {
+        Some(Box::new(self.interior(hit)))
+    }
+}
+
+// Metal with a complex index of refraction eta + ik, roughness can differ along
+// the two tangent directions for brushed looks
+pub struct Conductor {
+    pub eta: Box<dyn Texture>,
+    pub k: Box<dyn Texture>,
+    pub roughness_u: Box<dyn Texture>,
+    pub roughness_v: Box<dyn Texture>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: Float) -> Conductor {
+        Conductor {
+            eta: Box::new(eta),
+            k: Box::new(k),
+            roughness_u: Box::new(roughness),
+            roughness_v: Box::new(roughness),
+        }
+    }
+
+    pub fn gold(roughness: Float) -> Conductor {
+        Conductor::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: Float) -> Conductor {
+        Conductor::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: Float) -> Conductor {
+        Conductor::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: Float) -> Conductor {
+        Conductor::new(
+            Color::new(0.155, 0.116, 0.138),
+            Color::new(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+
+    fn conductor_bsdf(&self, hit: &Hit) -> ConductorBSDF {
+        ConductorBSDF {
+            frame: Frame::from_tangent(hit.normal, hit.dpdu),
+            distribution: TrowbridgeReitz::from_roughness(
+                self.roughness_u.value(hit),
+                self.roughness_v.value(hit),
+            ),
+            eta: self.eta.color(hit),
+            k: self.k.color(hit),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        let bsdf = self.conductor_bsdf(hit);
+        direct_lighting(scene, &bsdf, hit.point, hit.normal, -ray.direction)
+            + sampled_reflection(scene, &bsdf, hit, ray, bounces)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        fresnel_conductor(1., self.eta.color(hit), self.k.color(hit))
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        let bsdf = self.conductor_bsdf(hit);
+        sample_light(scene, light, &bsdf, hit.point, hit.normal, -ray.direction)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        Some(Box::new(self.conductor_bsdf(hit)))
+    }
+}
+
+// Artist friendly uber material, all sliders are on [0, 1]. When not path traced
+// only direct lighting and a single sampled reflection are taken into account.
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    pub specular: Box<dyn Texture>,
+    pub sheen: Box<dyn Texture>,
+    pub clearcoat: Box<dyn Texture>,
+    pub transmission: Box<dyn Texture>,
+    pub ior: Box<dyn Texture>,
+}
+
+impl Principled {
+    pub fn new(base_color: Box<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: Box::new(0.),
+            roughness: Box::new(0.5),
+            specular: Box::new(0.5),
+            sheen: Box::new(0.),
+            clearcoat: Box::new(0.),
+            transmission: Box::new(0.),
+            ior: Box::new(1.5),
+        }
+    }
+
+    fn principled_bsdf(&self, hit: &Hit) -> PrincipledBSDF<'static> {
+        let frame = Frame::from_tangent(hit.normal, hit.dpdu);
+        let roughness = self.roughness.value(hit);
+        let distribution = TrowbridgeReitz::from_roughness(roughness, roughness);
+        let ior = self.ior.value(hit);
+        let glass: Box<dyn BSDF> = if distribution.is_smooth() {
+            Box::new(SpecularDielectric {
+                normal: hit.normal,
+                ior,
+            })
+        } else {
+            Box::new(RoughDielectricBSDF {
+                frame,
+                distribution,
+                ior,
+            })
+        };
+
+        PrincipledBSDF {
+            frame,
+            base_color: self.base_color.color(hit),
+            metallic: self.metallic.value(hit),
+            specular: self.specular.value(hit),
+            sheen: self.sheen.value(hit),
+            clearcoat: self.clearcoat.value(hit),
+            transmission: self.transmission.value(hit),
+            distribution,
+            glass,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        let bsdf = self.principled_bsdf(hit);
+        direct_lighting(scene, &bsdf, hit.point, hit.normal, -ray.direction)
+            + sampled_reflection(scene, &bsdf, hit, ray, bounces)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.base_color.color(hit)
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        let bsdf = self.principled_bsdf(hit);
+        sample_light(scene, light, &bsdf, hit.point, hit.normal, -ray.direction)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        Some(Box::new(self.principled_bsdf(hit)))
+    }
+}
+
+// Ideal diffuse reflector, only direct lighting when not path traced
+pub struct Lambertian {
+    pub color: Box<dyn Texture>,
+}
+
+impl Lambertian {
+    fn lambertian_bsdf(&self, hit: &Hit) -> LambertianBSDF {
+        LambertianBSDF {
+            frame: Frame::from_normal(hit.normal),
+            albedo: self.color.color(hit),
+        }
+    }
+}
+
+impl Material for Lambertian {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, _bounces: u32) -> Color {
+        let bsdf = self.lambertian_bsdf(hit);
+        direct_lighting(scene, &bsdf, hit.point, hit.normal, -ray.direction)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.color.color(hit)
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        let bsdf = self.lambertian_bsdf(hit);
+        sample_light(scene, light, &bsdf, hit.point, hit.normal, -ray.direction)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        Some(Box::new(self.lambertian_bsdf(hit)))
+    }
+}
+
+// Weight of a layer in a MixedMaterial
+pub enum Mix {
+    Texture(Box<dyn Texture>),
+    // Reflectance of a dielectric interface with this index of refraction
+    Fresnel(Float),
+    // Grows from 0 facing the viewer to 1 at grazing angles, the value is the exponent
+    Facing(Float),
+    // One minus the other weight
+    Inverse(Box<Mix>),
+}
+
+impl Mix {
+    // wo points towards the viewer
+    pub fn value(&self, hit: &Hit, wo: Vector) -> Float {
+        let cos = (wo.normalize() * hit.normal).abs().min(1.);
+        match self {
+            Mix::Texture(texture) => texture.value(hit),
+            Mix::Fresnel(ior) => fresnel_dielectric(cos, *ior),
+            Mix::Facing(exponent) => (1. - cos).powf(*exponent),
+            Mix::Inverse(mix) => 1. - mix.value(hit, wo),
+        }
+    }
+}
+
+// Blend of any number of materials: every layer covers the base material with
+// its weight, weights adding up to more than one are normalized
+pub struct MixedMaterial {
+    pub base: Box<dyn Material>,
+    pub layers: Vec<(Box<dyn Material>, Mix)>,
+}
+
+impl MixedMaterial {
+    // Goes from material1 at 0 to material2 at 1
+    pub fn new(
+        material1: Box<dyn Material>,
+        material2: Box<dyn Material>,
+        mix: Mix,
+    ) -> MixedMaterial {
+        MixedMaterial {
+            base: material1,
+            layers: vec![(material2, mix)],
+        }
+    }
+
+    // Weights of the base followed by the layers
+    fn weights(&self, hit: &Hit, wo: Vector) -> Vec<Float> {
+        let mut weights = vec![0.];
+        weights.extend(
+            self.layers
+                .iter()
+                .map(|(_, mix)| mix.value(hit, wo).max(0.)),
+        );
+
+        let total: Float = weights.iter().sum();
+        if total > 1. {
+            for weight in weights.iter_mut() {
+                *weight /= total;
+            }
+        } else {
+            weights[0] = 1. - total;
+        }
+        weights
+    }
+
+    fn materials(&self) -> impl Iterator<Item = &dyn Material> {
+        std::iter::once(self.base.as_ref()).chain(self.layers.iter().map(|(m, _)| m.as_ref()))
+    }
+
+    // Sum of the weighted materials, skipping those without influence
+    fn mix(&self, hit: &Hit, wo: Vector, color: impl Fn(&dyn Material) -> Color) -> Color {
+        let mut total = Color::BLACK;
+        for (material, weight) in self.materials().zip(self.weights(hit, wo)) {
+            if weight > 0. {
+                total += weight * color(material);
+            }
+        }
+        total
+    }
+}
+
+impl Material for MixedMaterial {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        self.mix(hit, -ray.direction, |material| {
+            material.surface_color(scene, hit, ray, bounces)
+        })
+    }
+
+    // Without a viewer the weights are taken at normal incidence
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.mix(hit, hit.normal, |material| material.albedo(hit))
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        self.mix(hit, -ray.direction, |material| {
+            material.light_contribution(scene, light, hit, ray)
+        })
+    }
+
+    // Layers without a BSDF don't reflect any light
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        let mut bsdfs = Vec::new();
+        let mut layers = Vec::new();
+        for (i, material) in self.materials().enumerate() {
+            if let Some(bsdf) = material.bsdf(hit) {
+                bsdfs.push(bsdf);
+                layers.push(i);
+            }
+        }
+        if bsdfs.is_empty() {
+            return Option::None;
+        }
+
+        let hit = *hit;
+        Some(Box::new(MixBSDF {
+            bsdfs,
+            weights: Box::new(move |wo| {
+                let weights = self.weights(&hit, wo);
+                layers.iter().map(|&i| weights[i]).collect()
+            }),
+        }))
+    }
+
+    fn emitted(&self, hit: &Hit, ray: Ray) -> Color {
+        self.mix(hit, -ray.direction, |material| material.emitted(hit, ray))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.materials().any(|material| material.is_emissive())
+    }
+
+    // Shaded like the material weighing the most at normal incidence
+    fn shading(&self, hit: &Hit) -> Hit {
+        let weights = self.weights(hit, hit.normal);
+        let mut selected = self.base.as_ref();
+        let mut max = weights[0];
+        for ((material, _), &weight) in self.layers.iter().zip(&weights[1..]) {
+            if weight > max {
+                selected = material.as_ref();
+                max = weight;
+            }
+        }
+        selected.shading(hit)
+    }
+
+    // The interior belongs to the layers transmitting light, the others leave
+    // it unchanged
+    fn interior_transmittance(&self, hit: &Hit, distance: Float) -> Color {
+        self.materials()
+            .fold(Color::new(1., 1., 1.), |transmittance, material| {
+                transmittance * material.interior_transmittance(hit, distance)
+            })
+    }
+
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        self.mix(hit, -direction, |material| {
+            material.shadow_transmittance(hit, direction, ignore_refraction)
+        })
+    }
+
+    // The first layer with a medium fills the interior
+    fn medium(&self, hit: &Hit) -> Option<Box<dyn Medium + '_>> {
+        self.materials().find_map(|material| material.medium(hit))
+    }
+}
+
+// Thin see-through surface like a curtain or a leaf, light passes straight
+// through where it isn't opaque
+pub struct Transparent {
+    pub material: Box<dyn Material>,
+    pub opacity: Box<dyn Texture>,
+}
+
+impl Transparent {
+    fn opacity(&self, hit: &Hit) -> Float {
+        self.opacity.value(hit).clamp(0., 1.)
+    }
+}
+
+impl Material for Transparent {
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        let opacity = self.opacity(hit);
+        let mut color = Color::BLACK;
+        if opacity > 0. {
+            color += opacity * self.material.surface_color(scene, hit, ray, bounces);
+        }
+        if opacity < 1. {
+            let through = Ray {
+                origin: offset(hit.point, hit.normal, ray.direction),
+                direction: ray.direction,
+            };
+            color += (1. - opacity) * scene.color(through, bounces + 1);
+        }
+        color
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.opacity(hit) * self.material.albedo(hit)
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        self.opacity(hit) * self.material.light_contribution(scene, light, hit, ray)
+    }
+
+    // Passing through is a refraction without a change in the index. Materials
+    // without a BSDF are diffuse with their albedo where opaque.
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        let opacity = self.opacity(hit);
+        let opaque = self.material.bsdf(hit).unwrap_or_else(|| {
+            Box::new(LambertianBSDF {
+                frame: Frame::from_normal(hit.normal),
+                albedo: self.material.albedo(hit),
+            })
+        });
+        Some(Box::new(MixBSDF {
+            bsdfs: vec![
+                Box::new(SpecularDielectric {
+                    normal: hit.normal,
+                    ior: 1.,
+                }),
+                opaque,
+            ],
+            weights: Box::new(move |_| vec![1. - opacity, opacity]),
+        }))
+    }
+
+    fn emitted(&self, hit: &Hit, ray: Ray) -> Color {
+        self.opacity(hit) * self.material.emitted(hit, ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn shading(&self, hit: &Hit) -> Hit {
+        self.material.shading(hit)
+    }
+
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        let opacity = self.opacity(hit);
+        (1. - opacity) * Color::new(1., 1., 1.)
+            + opacity
+                * self
+                    .material
+                    .shadow_transmittance(hit, direction, ignore_refraction)
+    }
+}
+
+// Materials wrapping another one with a perturbed shading normal
+trait ShadingModifier {
+    fn base(&self) -> &dyn Material;
+    fn perturb(&self, hit: &Hit) -> Hit;
+}
+
+impl<T: Send + Sync> Material for T
+where
+    T: ShadingModifier,
+{
+    fn surface_color(&self, scene: &Scene, hit: &Hit, ray: Ray, bounces: u32) -> Color {
+        self.base()
+            .surface_color(scene, &self.perturb(hit), ray, bounces)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.base().albedo(&self.perturb(hit))
+    }
+
+    fn light_contribution(&self, scene: &Scene, light: &dyn Light, hit: &Hit, ray: Ray) -> Color {
+        self.base()
+            .light_contribution(scene, light, &self.perturb(hit), ray)
+    }
+
+    fn bsdf(&self, hit: &Hit) -> Option<Box<dyn BSDF + '_>> {
+        self.base().bsdf(&self.perturb(hit))
+    }
+
+    fn emitted(&self, hit: &Hit, ray: Ray) -> Color {
+        self.base().emitted(&self.perturb(hit), ray)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base().is_emissive()
+    }
+
+    fn shading(&self, hit: &Hit) -> Hit {
+        self.base().shading(&self.perturb(hit))
+    }
+
+    fn interior_transmittance(&self, hit: &Hit, distance: Float) -> Color {
+        self.base()
+            .interior_transmittance(&self.perturb(hit), distance)
+    }
+
+    fn shadow_transmittance(&self, hit: &Hit, direction: Vector, ignore_refraction: bool) -> Color {
+        self.base()
+            .shadow_transmittance(&self.perturb(hit), direction, ignore_refraction)
+    }
+
+    fn medium(&self, hit: &Hit) -> Option<Box<dyn Medium + '_>> {
+        self.base().medium(&self.perturb(hit))
+    }
+}
+
+// Perturbs the shading normal of a material with a tangent space normal map,
+// the texture encodes the normal's components as colors on [0, 1]
+pub struct NormalMap {
+    pub material: Box<dyn Material>,
+    pub map: Box<dyn Texture>,
+    // Scales the tangential components
+    pub strength: Float,
+}
+
+impl ShadingModifier for NormalMap {
+    fn base(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn perturb(&self, hit: &Hit) -> Hit {
+        // Any tangent frame if dpdu is degenerate or along the normal
+        let frame = Frame::from_tangent(hit.normal, hit.dpdu);
+        let (n, t) = (frame.n, frame.s);
+        let b = if frame.t * hit.dpdv < 0. {
+            -frame.t
+        } else {
+            frame.t
+        };
+
+        let c = self.map.color(hit);
+        let normal = self.strength * (2. * c.red - 1.) * t
+            + self.strength * (2. * c.green - 1.) * b
+            + (2. * c.blue - 1.) * n;
+        Hit {
+            normal: normal.normalize(),
+            ..*hit
+        }
+    }
+}
+
+// Step in texture space used to differentiate the height
+const BUMP_DELTA: Float = 1e-3;
+
+// Perturbs the shading normal of a material as if the surface was displaced
+// along the normal by the height texture
+pub struct BumpMap {
+    pub material: Box<dyn Material>,
+    pub height: Box<dyn Texture>,
+    pub scale: Float,
+}
+
+impl ShadingModifier for BumpMap {
+    fn base(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn perturb(&self, hit: &Hit) -> Hit {
+        let shifted_u = Hit {
+            point: hit.point + BUMP_DELTA * hit.dpdu,
+            uv: (hit.uv.0 + BUMP_DELTA, hit.uv.1),
+            ..*hit
+        };
+        let shifted_v = Hit {
+            point: hit.point + BUMP_DELTA * hit.dpdv,
+            uv: (hit.uv.0, hit.uv.1 + BUMP_DELTA),
+            ..*hit
+        };
+
+        let height = self.height.value(hit);
+        let dhdu = (self.height.value(&shifted_u) - height) / BUMP_DELTA;
+        let dhdv = (self.height.value(&shifted_v) - height) / BUMP_DELTA;
+        let dpdu = hit.dpdu + self.scale * dhdu * hit.normal;
+        let dpdv = hit.dpdv + self.scale * dhdv * hit.normal;
+
+        let normal = dpdu.cross(dpdv).normalize();
+        Hit {
+            normal: if normal * hit.normal < 0. {
+                -normal
+            } else {
+                normal
+            },
+            dpdu,
+            dpdv,
+            ..*hit
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_map_with_degenerate_tangent() {
+        let map = NormalMap {
+            material: Box::new(Lambertian {
+                color: Box::new(0.5),
+            }),
+            map: Box::new(Color::new(0.75, 0.5, 1.)),
+            strength: 1.,
+        };
+        // At a sphere pole dpdu vanishes, elsewhere it can lie along the normal
+        for &dpdu in &[Vector::new(0., 0., 0.), Vector::new(0., 0., 1.)] {
+            let hit = Hit {
+                point: Point::ORIGIN,
+                normal: Vector::new(0., 0., 1.),
+                uv: (0.5, 0.5),
+                dpdu,
+                dpdv: Vector::new(0., 1., 0.),
+                footprint: 0.,
+            };
+            let normal = map.perturb(&hit).normal;
+            assert!((normal.norm() - 1.).abs() < 1e-4, "{:?}", normal);
+            assert!(normal.z > 0.5 && normal.z < 1., "{:?}", normal);
+        }
+    }
+
+    #[test]
+    fn mixed_material_forwards_the_interior_and_shading() {
+        let hit = Hit {
+            point: Point::ORIGIN,
+            normal: Vector::new(0., 0., 1.),
+            uv: (0.5, 0.5),
+            dpdu: Vector::new(1., 0., 0.),
+            dpdv: Vector::new(0., 1., 0.),
+            footprint: 0.,
+        };
+        let lambertian = || -> Box<dyn Material> {
+            Box::new(Lambertian {
+                color: Box::new(0.5),
+            })
+        };
+        let mixed = MixedMaterial::new(
+            lambertian(),
+            Box::new(NormalMap {
+                material: Box::new(Subsurface::new(
+                    Box::new(0.5),
+                    Box::new(Color::new(1., 1., 1.)),
+                    Box::new(1.5),
+                )),
+                map: Box::new(Color::new(1., 0.5, 0.5)),
+                strength: 1.,
+            }),
+            Mix::Texture(Box::new(0.75)),
+        );
+        assert!(mixed.medium(&hit).is_some());
+        assert!(mixed.shading(&hit).normal.x > 0.5);
+
+        let mixed = MixedMaterial::new(
+            lambertian(),
+            Box::new(Dielectric {
+                ior: Box::new(1.5),
+                absorption: Box::new(1.),
+                roughness: Box::new(0.),
+                dispersion: Box::new(0.),
+            }),
+            Mix::Texture(Box::new(0.25)),
+        );
+        assert!(mixed.medium(&hit).is_none());
+        assert_eq!(mixed.shading(&hit).normal.z, 1.);
+        // Absorbed by the glass whatever its weight
+        let transmittance = mixed.interior_transmittance(&hit, 2.);
+        assert!((transmittance.red - Float::exp(-2.)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn transparent_phong_reflects_diffusely() {
+        let hit = Hit {
+            point: Point::ORIGIN,
+            normal: Vector::new(0., 0., 1.),
+            uv: (0.5, 0.5),
+            dpdu: Vector::new(1., 0., 0.),
+            dpdv: Vector::new(0., 1., 0.),
+            footprint: 0.,
+        };
+        let transparent = Transparent {
+            material: Box::new(PhongMaterial {
+                ambient_color: Box::new(Color::BLACK),
+                diffuse_color: Box::new(0.5),
+                specular_color: Box::new(Color::BLACK),
+                specular_power: Box::new(1.),
+            }),
+            opacity: Box::new(0.5),
+        };
+        let bsdf = transparent.bsdf(&hit).unwrap();
+        let wo = Vector::new(0., 0., 1.);
+        let wi = Vector::new(0.6, 0., 0.8);
+        let expected = 0.5 * 0.5 / crate::float::consts::PI;
+        assert!((bsdf.evaluate(wo, wi).green - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn subsurface_interior_from_textures() {
+        let hit = Hit {
+            point: Point::ORIGIN,
+            normal: Vector::new(0., 0., 1.),
+            uv: (0.5, 0.5),
+            dpdu: Vector::new(1., 0., 0.),
+            dpdv: Vector::new(0., 1., 0.),
+            footprint: 0.,
+        };
+        let subsurface = Subsurface::new(
+            Box::new(Color::new(1., 0., 0.5)),
+            Box::new(Color::new(0.5, 2., 0.)),
+            Box::new(1.3),
+        );
+        let interior = subsurface.interior(&hit);
+        let extinction = interior.absorption + interior.scattering;
+        assert!((extinction.red - 2.).abs() < 1e-4);
+        assert!((extinction.green - 0.5).abs() < 1e-4);
+        assert!(extinction.blue.is_finite());
+        // White doesn't absorb, black doesn't scatter
+        assert!(interior.absorption.red.abs() < 1e-4);
+        assert!(interior.scattering.green.abs() < 1e-4);
+        assert!(interior.scattering.blue > 0. && interior.absorption.blue > 0.);
     }
 }
@@ -0,0 +1,454 @@
+use crate::bsdf::Frame;
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::geometries::box_interval;
+use crate::light::FAR;
+use crate::noise::Noise;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampling::random;
+use crate::vector::Vector;
+
+// Directions point away from the scattering point: wo towards the viewer, wi
+// towards the light. g from -1 (backward) to 1 (forward scattering).
+#[derive(Copy, Clone, Debug)]
+pub struct HenyeyGreenstein {
+    pub g: Float,
+}
+
+impl HenyeyGreenstein {
+    pub fn evaluate(&self, wo: Vector, wi: Vector) -> Float {
+        let g = self.g;
+        let denominator = 1. + g * g + 2. * g * (wo * wi);
+        (1. - g * g) / (4. * PI * denominator * denominator.max(0.).sqrt())
+    }
+
+    // Exactly proportional to evaluate, which is also the pdf
+    pub fn sample(&self, wo: Vector, u: (Float, Float)) -> Vector {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u.0
+        } else {
+            let term = (1. - g * g) / (1. + g - 2. * g * u.0);
+            -(1. + g * g - term * term) / (2. * g)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let (sin_phi, cos_phi) = (2. * PI * u.1).sin_cos();
+        Frame::from_normal(wo).to_world(Vector::new(
+            sin_theta * cos_phi,
+            sin_theta * sin_phi,
+            cos_theta,
+        ))
+    }
+}
+
+// Absorbing and scattering volume, the ray directions are normalized
+pub trait Medium: Send + Sync {
+    // Fraction of light surviving along the ray up to the distance
+    fn transmittance(&self, ray: Ray, distance: Float) -> Color;
+
+    // Distance to a scattering event before the given one, if any, and the
+    // throughput factor of the sampled segment. Colored media favor the channels
+    // carrying most of the path's throughput, see Chiang et al. 2016.
+    fn sample(&self, ray: Ray, distance: Float, throughput: Color) -> (Option<Float>, Color);
+
+    fn phase(&self) -> HenyeyGreenstein;
+}
+
+// Lets media borrowed from the scene stand in for those built per hit, see
+// Scene::medium
+impl<M: Medium + ?Sized> Medium for &M {
+    fn transmittance(&self, ray: Ray, distance: Float) -> Color {
+        (**self).transmittance(ray, distance)
+    }
+
+    fn sample(&self, ray: Ray, distance: Float, throughput: Color) -> (Option<Float>, Color) {
+        (**self).sample(ray, distance, throughput)
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        (**self).phase()
+    }
+}
+
+// Beer-Lambert law, also for infinite distances through empty channels
+fn beer_lambert(extinction: Color, distance: Float) -> Color {
+    let channel = |sigma: Float| {
+        if sigma > 0. {
+            (-sigma * distance).exp()
+        } else {
+            1.
+        }
+    };
+    Color::new(
+        channel(extinction.red),
+        channel(extinction.green),
+        channel(extinction.blue),
+    )
+}
+
+// Average of the channels weighted by the throughput, or by the same amount
+// when it's black
+fn weighted_mean(color: Color, throughput: Color) -> Float {
+    let total = throughput.red + throughput.green + throughput.blue;
+    if total > 0. {
+        (color.red * throughput.red + color.green * throughput.green + color.blue * throughput.blue)
+            / total
+    } else {
+        (color.red + color.green + color.blue) / 3.
+    }
+}
+
+// Constant coefficients per unit length
+pub struct HomogeneousMedium {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub g: Float,
+}
+
+impl Medium for HomogeneousMedium {
+    fn transmittance(&self, _ray: Ray, distance: Float) -> Color {
+        beer_lambert(self.absorption + self.scattering, distance)
+    }
+
+    // Distances are sampled from the extinction of a channel chosen with the
+    // weights of weighted_mean
+    fn sample(&self, _ray: Ray, distance: Float, throughput: Color) -> (Option<Float>, Color) {
+        let extinction = self.absorption + self.scattering;
+        let u = random();
+        let sigma = if u < weighted_mean(Color::new(1., 0., 0.), throughput) {
+            extinction.red
+        } else if u < weighted_mean(Color::new(1., 1., 0.), throughput) {
+            extinction.green
+        } else {
+            extinction.blue
+        };
+        let t = if sigma > 0. {
+            -(1. - random()).ln() / sigma
+        } else {
+            Float::INFINITY
+        };
+
+        if t < distance {
+            let transmittance = beer_lambert(extinction, t);
+            let pdf = weighted_mean(extinction * transmittance, throughput);
+            (Some(t), self.scattering * transmittance * pdf.recip())
+        } else {
+            let transmittance = beer_lambert(extinction, distance);
+            let probability = weighted_mean(transmittance, throughput);
+            if probability > 0. {
+                (None, transmittance * probability.recip())
+            } else {
+                (None, Color::BLACK)
+            }
+        }
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        HenyeyGreenstein { g: self.g }
+    }
+}
+
+// Scalar field scaling the coefficients of a heterogeneous medium
+pub trait Density: Send + Sync {
+    fn density(&self, point: Point) -> Float;
+
+    // Upper bound of the density, used as majorant when tracking
+    fn max_density(&self) -> Float;
+
+    // Box outside of which the density is zero, None if unbounded
+    fn bounds(&self) -> Option<(Point, Point)> {
+        None
+    }
+}
+
+// Solid noise remapped to [0, 1]
+pub struct NoiseDensity {
+    pub noise: Box<dyn Noise>,
+    // Size of the features in world units
+    pub scale: Float,
+    // Box the density is confined to, None to fill the whole interior.
+    // Tracking skips the rest of the ray.
+    pub bounds: Option<(Point, Point)>,
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, point: Point) -> Float {
+        if let Some((lo, hi)) = self.bounds {
+            let inside = (lo.x..=hi.x).contains(&point.x)
+                && (lo.y..=hi.y).contains(&point.y)
+                && (lo.z..=hi.z).contains(&point.z);
+            if !inside {
+                return 0.;
+            }
+        }
+        let (min, max) = self.noise.range();
+        let point = Point::ORIGIN + point.into_vector() * self.scale.recip();
+        ((self.noise.noise(point) - min) / (max - min)).clamp(0., 1.)
+    }
+
+    fn max_density(&self) -> Float {
+        1.
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        self.bounds
+    }
+}
+
+// Densities on a regular grid spanning the box, interpolated trilinearly
+// between the voxel centers
+pub struct VoxelGrid {
+    min: Point,
+    max: Point,
+    resolution: (usize, usize, usize),
+    densities: Vec<Float>,
+    max_density: Float,
+}
+
+impl VoxelGrid {
+    // Densities ordered with x varying fastest, then y and z
+    pub fn new(
+        min: Point,
+        max: Point,
+        resolution: (usize, usize, usize),
+        densities: Vec<Float>,
+    ) -> VoxelGrid {
+        assert_eq!(densities.len(), resolution.0 * resolution.1 * resolution.2);
+        let max_density = densities.iter().copied().fold(0., Float::max);
+        VoxelGrid {
+            min,
+            max,
+            resolution,
+            densities,
+            max_density,
+        }
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> Float {
+        let (nx, ny, _) = self.resolution;
+        self.densities[(z * ny + y) * nx + x]
+    }
+}
+
+impl Density for VoxelGrid {
+    fn density(&self, point: Point) -> Float {
+        let (nx, ny, nz) = self.resolution;
+        // Continuous voxel coordinates, clamped to the outer centers
+        let axis = |p: Float, lo: Float, hi: Float, n: usize| {
+            let t = (p - lo) / (hi - lo);
+            if !(0. ..=1.).contains(&t) {
+                return None;
+            }
+            let x = (t * n as Float - 0.5).clamp(0., (n - 1) as Float);
+            let i = (x as usize).min(n.saturating_sub(2));
+            Some((i, (i + 1).min(n - 1), x - i as Float))
+        };
+        let (Some((x0, x1, tx)), Some((y0, y1, ty)), Some((z0, z1, tz))) = (
+            axis(point.x, self.min.x, self.max.x, nx),
+            axis(point.y, self.min.y, self.max.y, ny),
+            axis(point.z, self.min.z, self.max.z, nz),
+        ) else {
+            return 0.;
+        };
+
+        let lerp = |t: Float, a: Float, b: Float| a + t * (b - a);
+        let plane = |z| {
+            lerp(
+                ty,
+                lerp(tx, self.voxel(x0, y0, z), self.voxel(x1, y0, z)),
+                lerp(tx, self.voxel(x0, y1, z), self.voxel(x1, y1, z)),
+            )
+        };
+        lerp(tz, plane(z0), plane(z1))
+    }
+
+    fn max_density(&self) -> Float {
+        self.max_density
+    }
+
+    fn bounds(&self) -> Option<(Point, Point)> {
+        Some((self.min, self.max))
+    }
+}
+
+// Coefficients scaled by the density, tracked against a constant majorant
+pub struct HeterogeneousMedium {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub g: Float,
+    pub density: Box<dyn Density>,
+}
+
+impl HeterogeneousMedium {
+    // Majorant and the part of the ray where the density can be nonzero
+    fn interval(&self, ray: Ray, distance: Float) -> Option<(Float, Float, Float)> {
+        let majorant =
+            (self.absorption + self.scattering).max_component() * self.density.max_density();
+        if majorant <= 0. {
+            return None;
+        }
+        let (near, far) = match self.density.bounds() {
+            Some((min, max)) => box_interval(min, max, ray, distance)?,
+            None => (0., distance.min(FAR)),
+        };
+        Some((majorant, near, far))
+    }
+}
+
+impl Medium for HeterogeneousMedium {
+    // Ratio tracking, terminated with Russian roulette once little light is left
+    fn transmittance(&self, ray: Ray, distance: Float) -> Color {
+        let (majorant, mut t, far) = match self.interval(ray, distance) {
+            Some(interval) => interval,
+            None => return Color::new(1., 1., 1.),
+        };
+        let extinction = self.absorption + self.scattering;
+        let mut transmittance = Color::new(1., 1., 1.);
+        loop {
+            t -= (1. - random()).ln() / majorant;
+            if t >= far {
+                return transmittance;
+            }
+            let density = self.density.density(ray.origin + t * ray.direction);
+            let null = Color::new(1., 1., 1.) - extinction * (density / majorant);
+            transmittance *= null;
+
+            let q = transmittance.max_component();
+            if q < 0.1 {
+                if random() >= q {
+                    return Color::BLACK;
+                }
+                transmittance /= q;
+            }
+        }
+    }
+
+    // Delta tracking with the collision type chosen by the weighted average of the
+    // coefficients and weights for the colored ones, see Kutz et al. 2017
+    fn sample(&self, ray: Ray, distance: Float, throughput: Color) -> (Option<Float>, Color) {
+        let (majorant, mut t, far) = match self.interval(ray, distance) {
+            Some(interval) => interval,
+            None => return (None, Color::new(1., 1., 1.)),
+        };
+        let extinction = self.absorption + self.scattering;
+        let mut weight = Color::new(1., 1., 1.);
+        loop {
+            t -= (1. - random()).ln() / majorant;
+            if t >= far {
+                return (None, weight);
+            }
+            let density = self.density.density(ray.origin + t * ray.direction);
+            let absorption = self.absorption * density;
+            let scattering = self.scattering * density;
+            let null = Color::new(majorant, majorant, majorant) - extinction * density;
+
+            let p_absorption = weighted_mean(absorption, throughput * weight) / majorant;
+            let p_scattering = weighted_mean(scattering, throughput * weight) / majorant;
+            let u = random();
+            if u < p_absorption {
+                return (None, Color::BLACK);
+            }
+            if u < p_absorption + p_scattering {
+                return (
+                    Some(t),
+                    weight * scattering * (majorant * p_scattering).recip(),
+                );
+            }
+            let p_null = 1. - p_absorption - p_scattering;
+            weight *= null * (majorant * p_null).recip();
+        }
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        HenyeyGreenstein { g: self.g }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn henyey_greenstein_pdf() {
+        let wo = Vector::new(0., 0.6, 0.8);
+        for g in [-0.5, 0., 0.3] {
+            let phase = HenyeyGreenstein { g };
+
+            // Integral of the pdf and of the mean cosine with wo, over the sphere
+            // uniform in cos(theta) and phi around wo
+            let frame = Frame::from_normal(wo);
+            let n = 400;
+            let (mut integral, mut mean_cosine) = (0., 0.);
+            for i in 0..n {
+                let z = 2. * (i as Float + 0.5) / n as Float - 1.;
+                let r = (1. - z * z).sqrt();
+                for j in 0..n {
+                    let (s, c) = (2. * PI * (j as Float + 0.5) / n as Float).sin_cos();
+                    let wi = frame.to_world(Vector::new(r * c, r * s, z));
+                    let pdf = phase.evaluate(wo, wi);
+                    integral += pdf;
+                    mean_cosine += pdf * (wo * wi);
+                }
+            }
+            let area = 4. * PI / (n * n) as Float;
+            assert!((integral * area - 1.).abs() < 1e-2);
+
+            // Samples are distributed with the pdf
+            let mut sampled = 0.;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = (
+                        (i as Float + 0.5) / n as Float,
+                        (j as Float + 0.5) / n as Float,
+                    );
+                    sampled += wo * phase.sample(wo, u);
+                }
+            }
+            sampled /= (n * n) as Float;
+            assert!((sampled - mean_cosine * area).abs() < 1e-2);
+        }
+    }
+
+    // Constant noise, the density is one wherever it's bounded to
+    struct Flat;
+
+    impl Noise for Flat {
+        fn noise(&self, _point: Point) -> Float {
+            1.
+        }
+
+        fn range(&self) -> (Float, Float) {
+            (0., 1.)
+        }
+    }
+
+    #[test]
+    fn bounded_noise_density() {
+        let medium = HeterogeneousMedium {
+            absorption: Color::new(1., 1., 1.),
+            scattering: Color::BLACK,
+            g: 0.,
+            density: Box::new(NoiseDensity {
+                noise: Box::new(Flat),
+                scale: 1.,
+                bounds: Some((Point::new(-1., -1., -1.), Point::new(1., 1., 1.))),
+            }),
+        };
+        assert_eq!(medium.density.density(Point::new(0., 0., 2.)), 0.);
+
+        // Only the two units inside the box absorb
+        let ray = Ray {
+            origin: Point::new(0., 0., -10.),
+            direction: Vector::new(0., 0., 1.),
+        };
+        assert_eq!(medium.interval(ray, 20.), Some((1., 9., 11.)));
+        let n = 20000;
+        let mean = (0..n)
+            .map(|_| medium.transmittance(ray, 20.).green)
+            .sum::<Float>()
+            / n as Float;
+        assert!((mean - Float::exp(-2.)).abs() < 0.02, "{}", mean);
+    }
+}
@@ -0,0 +1,430 @@
+use crate::bsdf::{
+    fresnel_conductor, fresnel_dielectric, reflect, refract, BSDFSample, Frame, BSDF,
+};
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::sampling::random;
+use crate::vector::Vector;
+
+// Below this the distribution is treated as a perfect mirror
+const SMOOTH_ALPHA: Float = 1e-3;
+
+// GGX distribution of microfacet normals, all directions in the local frame
+#[derive(Copy, Clone, Debug)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: Float,
+    pub alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    // Perceptually linear roughness on [0, 1]
+    pub fn from_roughness(roughness_u: Float, roughness_v: Float) -> TrowbridgeReitz {
+        TrowbridgeReitz {
+            alpha_x: roughness_u * roughness_u,
+            alpha_y: roughness_v * roughness_v,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    // cos²(phi) and tan²(theta) of a direction
+    fn angles(w: Vector) -> (Float, Float) {
+        let cos2_theta = w.z * w.z;
+        let sin2_theta = (1. - cos2_theta).max(0.);
+        let cos2_phi = if sin2_theta == 0. {
+            1.
+        } else {
+            (w.x * w.x / sin2_theta).min(1.)
+        };
+        (cos2_phi, sin2_theta / cos2_theta)
+    }
+
+    pub fn d(&self, wm: Vector) -> Float {
+        let (cos2_phi, tan2_theta) = Self::angles(wm);
+        if !tan2_theta.is_finite() {
+            return 0.;
+        }
+
+        let cos4_theta = (wm.z * wm.z).powi(2);
+        let e = tan2_theta
+            * (cos2_phi / (self.alpha_x * self.alpha_x)
+                + (1. - cos2_phi) / (self.alpha_y * self.alpha_y));
+        (PI * self.alpha_x * self.alpha_y * cos4_theta * (1. + e).powi(2)).recip()
+    }
+
+    pub fn lambda(&self, w: Vector) -> Float {
+        let (cos2_phi, tan2_theta) = Self::angles(w);
+        if !tan2_theta.is_finite() {
+            return 0.;
+        }
+
+        let alpha2 =
+            cos2_phi * self.alpha_x * self.alpha_x + (1. - cos2_phi) * self.alpha_y * self.alpha_y;
+        ((1. + alpha2 * tan2_theta).sqrt() - 1.) / 2.
+    }
+
+    pub fn g1(&self, w: Vector) -> Float {
+        (1. + self.lambda(w)).recip()
+    }
+
+    // Smith height-correlated masking-shadowing
+    pub fn g(&self, wo: Vector, wi: Vector) -> Float {
+        (1. + self.lambda(wo) + self.lambda(wi)).recip()
+    }
+
+    // Distribution of normals visible from w
+    pub fn d_visible(&self, w: Vector, wm: Vector) -> Float {
+        if w.z == 0. {
+            return 0.;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * (w * wm).abs()
+    }
+
+    // Samples a visible normal, see Heitz 2018
+    pub fn sample_wm(&self, w: Vector, u: (Float, Float)) -> Vector {
+        // Transform to the hemisphere configuration
+        let mut wh = Vector::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0. {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z < 0.99999 {
+            Vector::K.cross(wh).normalize()
+        } else {
+            Vector::I
+        };
+        let t2 = wh.cross(t1);
+
+        // Uniformly sample the projected, warped disk
+        let r = u.0.sqrt();
+        let (s, c) = (2. * PI * u.1).sin_cos();
+        let (px, py) = (r * c, r * s);
+        let h = (1. - px * px).sqrt();
+        let t = (1. + wh.z) / 2.;
+        let py = (1. - t) * h + t * py;
+        let pz = (1. - px * px - py * py).max(0.).sqrt();
+
+        // Transform back to the ellipsoid configuration
+        let nh = px * t1 + py * t2 + pz * wh;
+        Vector::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+
+    pub fn pdf(&self, w: Vector, wm: Vector) -> Float {
+        self.d_visible(w, wm)
+    }
+}
+
+// Metals, two-sided
+pub struct ConductorBSDF {
+    pub frame: Frame,
+    pub distribution: TrowbridgeReitz,
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ConductorBSDF {
+    // Local directions, mirrored so wo lies in the upper hemisphere
+    fn local(&self, wo: Vector, wi: Vector) -> (Vector, Vector) {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        if wo.z < 0. {
+            (-wo, -wi)
+        } else {
+            (wo, wi)
+        }
+    }
+}
+
+impl BSDF for ConductorBSDF {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color {
+        let (wo, wi) = self.local(wo, wi);
+        if self.distribution.is_smooth() || wo.z <= 0. || wi.z <= 0. {
+            return Color::BLACK;
+        }
+
+        let wm = (wo + wi).normalize();
+        if wm.norm_squared() == 0. {
+            return Color::BLACK;
+        }
+
+        let fresnel = fresnel_conductor((wo * wm).abs(), self.eta, self.k);
+        fresnel * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4. * wo.z * wi.z))
+    }
+
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample> {
+        let wo_local = self.frame.to_local(wo);
+        let flip = wo_local.z < 0.;
+        let wo_local = if flip { -wo_local } else { wo_local };
+        if wo_local.z == 0. {
+            return None;
+        }
+        let to_world = |w: Vector| self.frame.to_world(if flip { -w } else { w });
+
+        if self.distribution.is_smooth() {
+            let wi = Vector::new(-wo_local.x, -wo_local.y, wo_local.z);
+            return Some(BSDFSample {
+                direction: to_world(wi),
+                value: fresnel_conductor(wi.z, self.eta, self.k) * wi.z.recip(),
+                pdf: 1.,
+                specular: true,
+            });
+        }
+
+        let wm = self.distribution.sample_wm(wo_local, u);
+        let wi = reflect(wo_local, wm);
+        if wi.z <= 0. {
+            return None;
+        }
+
+        let pdf = self.distribution.pdf(wo_local, wm) / (4. * (wo_local * wm).abs());
+        let fresnel = fresnel_conductor((wo_local * wm).abs(), self.eta, self.k);
+        let value = fresnel
+            * (self.distribution.d(wm) * self.distribution.g(wo_local, wi)
+                / (4. * wo_local.z * wi.z));
+
+        Some(BSDFSample {
+            direction: to_world(wi),
+            value,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float {
+        let (wo, wi) = self.local(wo, wi);
+        if self.distribution.is_smooth() || wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+
+        let wm = (wo + wi).normalize();
+        if wm.norm_squared() == 0. {
+            return 0.;
+        }
+        self.distribution.pdf(wo, wm) / (4. * (wo * wm).abs())
+    }
+}
+
+// Rough glass, the frame normal points to the outside of the medium
+pub struct RoughDielectricBSDF {
+    pub frame: Frame,
+    pub distribution: TrowbridgeReitz,
+    pub ior: Float,
+}
+
+impl RoughDielectricBSDF {
+    // Generalized half vector facing +z and the relative index of refraction
+    fn half_vector(&self, wo: Vector, wi: Vector) -> Option<(Vector, Float)> {
+        let reflection = wo.z * wi.z > 0.;
+        let etap = if reflection {
+            1.
+        } else if wo.z > 0. {
+            self.ior
+        } else {
+            self.ior.recip()
+        };
+
+        let wm = wi * etap + wo;
+        if wo.z == 0. || wi.z == 0. || wm.norm_squared() == 0. {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.z < 0. { -wm } else { wm };
+
+        // Discard backfacing microfacets
+        if (wm * wi) * wi.z < 0. || (wm * wo) * wo.z < 0. {
+            return None;
+        }
+        Some((wm, etap))
+    }
+}
+
+impl BSDF for RoughDielectricBSDF {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return Color::BLACK,
+        };
+
+        let fresnel = fresnel_dielectric(wo * wm, self.ior);
+        let value = if etap == 1. {
+            self.distribution.d(wm) * self.distribution.g(wo, wi) * fresnel
+                / (4. * wi.z * wo.z).abs()
+        } else {
+            let denom = (wi * wm + (wo * wm) / etap).powi(2) * wi.z * wo.z;
+            self.distribution.d(wm)
+                * (1. - fresnel)
+                * self.distribution.g(wo, wi)
+                * ((wi * wm) * (wo * wm) / denom).abs()
+                / (etap * etap)
+        };
+        Color::new(value, value, value)
+    }
+
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample> {
+        let wo_local = self.frame.to_local(wo);
+        if wo_local.z == 0. {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(wo_local, u);
+        let reflectance = fresnel_dielectric(wo_local * wm, self.ior);
+
+        let (wi, etap) = if random() < reflectance {
+            (reflect(wo_local, wm), 1.)
+        } else if wo_local * wm >= 0. {
+            (refract(wo_local, wm, self.ior)?, self.ior)
+        } else {
+            (refract(wo_local, -wm, self.ior.recip())?, self.ior.recip())
+        };
+
+        // Reflections have to stay on the same side, refractions have to cross
+        if wi.z == 0. || (wo_local.z * wi.z > 0.) != (etap == 1.) {
+            return None;
+        }
+
+        let visible = self.distribution.pdf(wo_local, wm);
+        let g = self.distribution.g(wo_local, wi);
+        let (value, pdf) = if etap == 1. {
+            let value = self.distribution.d(wm) * g * reflectance / (4. * wi.z * wo_local.z).abs();
+            (value, visible / (4. * (wo_local * wm).abs()) * reflectance)
+        } else {
+            let denom = (wi * wm + (wo_local * wm) / etap).powi(2);
+            let dwm_dwi = (wi * wm).abs() / denom;
+            let value = self.distribution.d(wm)
+                * (1. - reflectance)
+                * g
+                * ((wi * wm) * (wo_local * wm) / (wi.z * wo_local.z * denom)).abs()
+                / (etap * etap);
+            (value, visible * dwm_dwi * (1. - reflectance))
+        };
+
+        Some(BSDFSample {
+            direction: self.frame.to_world(wi),
+            value: Color::new(value, value, value),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.,
+        };
+
+        let reflectance = fresnel_dielectric(wo * wm, self.ior);
+        if etap == 1. {
+            self.distribution.pdf(wo, wm) / (4. * (wo * wm).abs()) * reflectance
+        } else {
+            let denom = (wi * wm + (wo * wm) / etap).powi(2);
+            let dwm_dwi = (wi * wm).abs() / denom;
+            self.distribution.pdf(wo, wm) * dwm_dwi * (1. - reflectance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integral over the hemisphere, uniform in cos(theta) and phi
+    fn integrate(f: impl Fn(Vector) -> Float) -> Float {
+        let n = 400;
+        let mut sum = 0.;
+        for i in 0..n {
+            let z = (i as Float + 0.5) / n as Float;
+            let r = (1. - z * z).sqrt();
+            for j in 0..n {
+                let (s, c) = (2. * PI * (j as Float + 0.5) / n as Float).sin_cos();
+                sum += f(Vector::new(r * c, r * s, z));
+            }
+        }
+        sum * 2. * PI / (n * n) as Float
+    }
+
+    #[test]
+    fn visible_normal_pdf() {
+        let distribution = TrowbridgeReitz {
+            alpha_x: 0.5,
+            alpha_y: 0.3,
+        };
+        for w in [Vector::new(0., 0., 1.), Vector::new(0.6, 0., 0.8)] {
+            let pdf = |wm: Vector| {
+                if w * wm > 0. {
+                    distribution.pdf(w, wm)
+                } else {
+                    0.
+                }
+            };
+            assert!((integrate(pdf) - 1.).abs() < 1e-2);
+
+            // Samples are distributed with the pdf
+            let n = 200;
+            let mut mean = 0.;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = (
+                        (i as Float + 0.5) / n as Float,
+                        (j as Float + 0.5) / n as Float,
+                    );
+                    let wm = distribution.sample_wm(w, u);
+                    assert!(w * wm >= 0. && wm.z > 0.);
+                    mean += wm.z;
+                }
+            }
+            mean /= (n * n) as Float;
+            assert!((mean - integrate(|wm| wm.z * pdf(wm))).abs() < 1e-2);
+        }
+    }
+
+    // Sampled values and pdfs match the evaluated ones
+    fn check_samples(bsdf: &dyn BSDF, wo: Vector) {
+        let close = |a: Float, b: Float| (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1.);
+        let n = 32;
+        for i in 0..n {
+            for j in 0..n {
+                let u = (
+                    (i as Float + 0.5) / n as Float,
+                    (j as Float + 0.5) / n as Float,
+                );
+                let sample = match bsdf.sample(wo, u) {
+                    Some(sample) if sample.pdf > 0. => sample,
+                    _ => continue,
+                };
+                assert!(!sample.specular);
+                assert!(close(sample.pdf, bsdf.pdf(wo, sample.direction)));
+                let value = bsdf.evaluate(wo, sample.direction);
+                assert!(close(sample.value.red, value.red));
+                assert!(close(sample.value.blue, value.blue));
+            }
+        }
+    }
+
+    #[test]
+    fn rough_bsdf_samples() {
+        let frame = Frame::from_normal(Vector::new(0., 0.6, 0.8));
+        let distribution = TrowbridgeReitz::from_roughness(0.6, 0.4);
+        let conductor = ConductorBSDF {
+            frame,
+            distribution,
+            eta: Color::new(0.2, 0.9, 1.1),
+            k: Color::new(3.9, 2.4, 2.2),
+        };
+        let glass = RoughDielectricBSDF {
+            frame,
+            distribution,
+            ior: 1.5,
+        };
+        for wo in [
+            Vector::new(0.6, 0., 0.8),
+            Vector::new(0.3, -0.5, -0.8).normalize(),
+        ] {
+            check_samples(&conductor, wo);
+            check_samples(&glass, wo);
+        }
+    }
+}
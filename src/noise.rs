@@ -0,0 +1,343 @@
+use crate::float::Float;
+use crate::point::Point;
+use crate::vector::Vector;
+
+// Solid noise functions, the same seed always gives the same pattern
+pub trait Noise: Send + Sync {
+    fn noise(&self, point: Point) -> Float;
+
+    // Approximate range of the values, used to normalize textures
+    fn range(&self) -> (Float, Float) {
+        (-1., 1.)
+    }
+}
+
+// Stafford's variant 13 finalizer
+fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Shuffled 0..=255, repeated to avoid wrapping the indices
+fn permutation(seed: u64) -> [u8; 512] {
+    let mut table = [0; 512];
+    for (i, entry) in table.iter_mut().take(256).enumerate() {
+        *entry = i as u8;
+    }
+
+    let mut state = seed;
+    for i in (1..256).rev() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let j = (mix64(state) % (i as u64 + 1)) as usize;
+        table.swap(i, j);
+    }
+
+    let (first, second) = table.split_at_mut(256);
+    second.copy_from_slice(first);
+    table
+}
+
+fn fade(t: Float) -> Float {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: Float, a: Float, b: Float) -> Float {
+    a + t * (b - a)
+}
+
+// Dot product with one of the twelve cube edge directions
+fn gradient(hash: u8, x: Float, y: Float, z: Float) -> Float {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Improved gradient noise, see Perlin 2002
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        Perlin {
+            permutation: permutation(seed),
+        }
+    }
+}
+
+impl Noise for Perlin {
+    fn noise(&self, point: Point) -> Float {
+        let p = &self.permutation;
+        let (fx, fy, fz) = (point.x.floor(), point.y.floor(), point.z.floor());
+        let (x, y, z) = (point.x - fx, point.y - fy, point.z - fz);
+        let (xi, yi, zi) = (
+            (fx as i64 & 255) as usize,
+            (fy as i64 & 255) as usize,
+            (fz as i64 & 255) as usize,
+        );
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] as usize + yi;
+        let (aa, ab) = (p[a] as usize + zi, p[a + 1] as usize + zi);
+        let b = p[xi + 1] as usize + yi;
+        let (ba, bb) = (p[b] as usize + zi, p[b + 1] as usize + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, gradient(p[aa], x, y, z), gradient(p[ba], x - 1., y, z)),
+                lerp(
+                    u,
+                    gradient(p[ab], x, y - 1., z),
+                    gradient(p[bb], x - 1., y - 1., z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    gradient(p[aa + 1], x, y, z - 1.),
+                    gradient(p[ba + 1], x - 1., y, z - 1.),
+                ),
+                lerp(
+                    u,
+                    gradient(p[ab + 1], x, y - 1., z - 1.),
+                    gradient(p[bb + 1], x - 1., y - 1., z - 1.),
+                ),
+            ),
+        )
+    }
+}
+
+// Gradient noise on a simplex grid, fewer directional artifacts than Perlin,
+// see Gustavson 2005
+pub struct Simplex {
+    permutation: [u8; 512],
+}
+
+impl Simplex {
+    pub fn new(seed: u64) -> Simplex {
+        Simplex {
+            permutation: permutation(seed),
+        }
+    }
+}
+
+impl Noise for Simplex {
+    fn noise(&self, point: Point) -> Float {
+        const F3: Float = 1. / 3.;
+        const G3: Float = 1. / 6.;
+        let p = &self.permutation;
+
+        // Skew to find the containing simplex cell
+        let s = (point.x + point.y + point.z) * F3;
+        let (i, j, k) = (
+            (point.x + s).floor(),
+            (point.y + s).floor(),
+            (point.z + s).floor(),
+        );
+        let t = (i + j + k) * G3;
+        let x0 = point.x - (i - t);
+        let y0 = point.y - (j - t);
+        let z0 = point.z - (k - t);
+
+        // Order of the remaining corners
+        let (i1, j1, k1, i2, j2, k2) = if x0 >= y0 {
+            if y0 >= z0 {
+                (1, 0, 0, 1, 1, 0)
+            } else if x0 >= z0 {
+                (1, 0, 0, 1, 0, 1)
+            } else {
+                (0, 0, 1, 1, 0, 1)
+            }
+        } else if y0 < z0 {
+            (0, 0, 1, 0, 1, 1)
+        } else if x0 < z0 {
+            (0, 1, 0, 0, 1, 1)
+        } else {
+            (0, 1, 0, 1, 1, 0)
+        };
+
+        let corners = [
+            (0, 0, 0, 0.),
+            (i1, j1, k1, G3),
+            (i2, j2, k2, 2. * G3),
+            (1, 1, 1, 3. * G3),
+        ];
+
+        let (ii, jj, kk) = (
+            (i as i64 & 255) as usize,
+            (j as i64 & 255) as usize,
+            (k as i64 & 255) as usize,
+        );
+        let mut total = 0.;
+        for (ci, cj, ck, offset) in corners {
+            let x = x0 - ci as Float + offset;
+            let y = y0 - cj as Float + offset;
+            let z = z0 - ck as Float + offset;
+            let t = 0.6 - x * x - y * y - z * z;
+            if t > 0. {
+                let hash = p[ii + ci + p[jj + cj + p[kk + ck] as usize] as usize];
+                total += t.powi(4) * gradient(hash, x, y, z);
+            }
+        }
+        32. * total
+    }
+}
+
+// Cellular noise: distance to the nearest of randomly scattered feature points,
+// one per unit cell
+pub struct Worley {
+    pub seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Worley {
+        Worley { seed }
+    }
+
+    fn feature_point(&self, cell: (i64, i64, i64)) -> Point {
+        let hash = mix64(
+            self.seed
+                ^ (cell.0 as u64).wrapping_mul(0x8da6_b343)
+                ^ (cell.1 as u64).wrapping_mul(0xd816_3841)
+                ^ (cell.2 as u64).wrapping_mul(0xcb1a_b31f),
+        );
+        let component = |shift: u32| ((hash >> shift) & 0x1f_ffff) as Float / 0x20_0000 as Float;
+        Point::new(
+            cell.0 as Float + component(0),
+            cell.1 as Float + component(21),
+            cell.2 as Float + component(42),
+        )
+    }
+
+    // Distances to the nearest and second nearest feature points
+    pub fn distances(&self, point: Point) -> (Float, Float) {
+        let cell = (
+            point.x.floor() as i64,
+            point.y.floor() as i64,
+            point.z.floor() as i64,
+        );
+
+        let (mut f1, mut f2) = (Float::INFINITY, Float::INFINITY);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let feature = self.feature_point((cell.0 + dx, cell.1 + dy, cell.2 + dz));
+                    let distance = (feature - point).norm();
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl Noise for Worley {
+    fn noise(&self, point: Point) -> Float {
+        self.distances(point).0
+    }
+
+    fn range(&self) -> (Float, Float) {
+        (0., 1.)
+    }
+}
+
+// Octaves are shifted so their lattices don't line up at the origin
+fn octave_point(point: Point, octave: u32, frequency: Float) -> Point {
+    let shift = octave as Float * Vector::new(19.19, 7.37, 13.71);
+    Point::ORIGIN + frequency * point.into_vector() + shift
+}
+
+// Fractal Brownian motion, sums octaves of increasing frequency and
+// decreasing amplitude
+pub struct FBm {
+    pub noise: Box<dyn Noise>,
+    pub octaves: u32,
+    pub lacunarity: Float,
+    pub gain: Float,
+}
+
+impl FBm {
+    pub fn new(noise: Box<dyn Noise>, octaves: u32) -> FBm {
+        FBm {
+            noise,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Noise for FBm {
+    fn noise(&self, point: Point) -> Float {
+        let (mut total, mut norm) = (0., 0.);
+        let (mut frequency, mut amplitude) = (1., 1.);
+        for octave in 0..self.octaves {
+            total += amplitude * self.noise.noise(octave_point(point, octave, frequency));
+            norm += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        total / norm
+    }
+
+    fn range(&self) -> (Float, Float) {
+        self.noise.range()
+    }
+}
+
+// Like FBm but with the absolute value of each octave, gives sharp creases
+pub struct Turbulence {
+    pub noise: Box<dyn Noise>,
+    pub octaves: u32,
+    pub lacunarity: Float,
+    pub gain: Float,
+}
+
+impl Turbulence {
+    pub fn new(noise: Box<dyn Noise>, octaves: u32) -> Turbulence {
+        Turbulence {
+            noise,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Noise for Turbulence {
+    fn noise(&self, point: Point) -> Float {
+        let (mut total, mut norm) = (0., 0.);
+        let (mut frequency, mut amplitude) = (1., 1.);
+        for octave in 0..self.octaves {
+            total += amplitude
+                * self
+                    .noise
+                    .noise(octave_point(point, octave, frequency))
+                    .abs();
+            norm += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        total / norm
+    }
+
+    fn range(&self) -> (Float, Float) {
+        (0., 1.)
+    }
+}
@@ -1,7 +1,99 @@
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
 use crate::geometries::Geometry;
+use crate::light::{mean, Light, LightBounds};
 use crate::materials::Material;
+use crate::medium::Medium;
+use crate::point::Point;
+use crate::ray::Ray;
+use crate::sampling::random2;
+use crate::scene::Scene;
+use crate::spectrum::{illuminant, reflectance};
+use crate::vector::Vector;
 
 pub struct Object {
     pub geometry: Box<dyn Geometry>,
     pub material: Box<dyn Material>,
+    // Fills the interior, a Dielectric with an ior of 1 makes the boundary invisible
+    pub medium: Option<Box<dyn Medium>>,
+}
+
+// Emissive objects are sampled over the surface of their geometry, those that
+// can't be sampled (like planes and SDFs) only light the scene when hit. Many of
+// them are better added to a LightBVH in Scene::lights than to Scene::objects,
+// they are then hit like other lights and don't cast shadows.
+impl Light for Object {
+    fn sample(&self, scene: &Scene, point: Point) -> (Color, Ray) {
+        let sample = match self.geometry.sample(point, random2()) {
+            Some(sample) if sample.pdf > 0. => sample,
+            _ => {
+                let to_obj = Ray {
+                    origin: point,
+                    direction: Vector::K,
+                };
+                return (Color::BLACK, to_obj);
+            }
+        };
+
+        let to_obj = Ray {
+            origin: sample.point,
+            direction: (point - sample.point).normalize(),
+        };
+        // As seen from the point
+        let ray = Ray {
+            origin: point,
+            direction: -to_obj.direction,
+        };
+        let hit = self.geometry.part_hit(sample.point, sample.part);
+        let radiance = illuminant(self.material.emitted(&hit, ray))
+            * reflectance(scene.see_surface(point, sample.point));
+        (radiance * sample.pdf.recip(), to_obj)
+    }
+
+    fn intersect(&self, ray: Ray) -> Option<(Float, Color)> {
+        let (distance, part) = self.geometry.intersect_part(ray)?;
+        let hit = self
+            .geometry
+            .part_hit(ray.origin + distance * ray.direction, part);
+        Some((distance, illuminant(self.material.emitted(&hit, ray))))
+    }
+
+    fn pdf(&self, point: Point, direction: Vector) -> Float {
+        self.geometry.pdf(point, direction)
+    }
+
+    // The radiance is averaged over a few points seen from outside the bounds,
+    // both sides may emit
+    fn bounds(&self) -> Option<LightBounds> {
+        let (min, max) = self.geometry.bounds()?;
+        let (axis, cos_theta_o) = self.geometry.normal_cone();
+        let reference = min + 0.5 * (max - min) + (max - min).norm() * axis;
+        let mut radiance = Color::BLACK;
+        let mut count = 0;
+        for i in 0..16 {
+            let u = (((i % 4) as Float + 0.5) / 4., ((i / 4) as Float + 0.5) / 4.);
+            if let Some(sample) = self.geometry.sample(reference, u) {
+                let ray = Ray {
+                    origin: reference,
+                    direction: (sample.point - reference).normalize(),
+                };
+                let hit = self.geometry.part_hit(sample.point, sample.part);
+                radiance += self.material.emitted(&hit, ray);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            radiance /= count as Float;
+        }
+        Some(LightBounds {
+            min,
+            max,
+            axis,
+            cos_theta_o,
+            cos_theta_e: 0.,
+            power: 2. * PI * mean(radiance) * self.geometry.area(),
+            two_sided: true,
+        })
+    }
 }
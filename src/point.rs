@@ -1,33 +1,43 @@
+use crate::float::{Float, Scalar};
 use crate::vector::Vector;
 use std::ops::{Add, Sub};
 
 #[derive(Copy, Clone, Debug)]
-pub struct Point {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+pub struct Point<T = Float> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
 }
 
-impl Point {
-    pub const ORIGIN: Point = Point {
-        x: 0.,
-        y: 0.,
-        z: 0.,
+impl<T: Scalar> Point<T> {
+    pub const ORIGIN: Point<T> = Point {
+        x: T::ZERO,
+        y: T::ZERO,
+        z: T::ZERO,
     };
 
-    pub fn new(x: f64, y: f64, z: f64) -> Point {
+    pub fn new(x: T, y: T, z: T) -> Point<T> {
         Point { x, y, z }
     }
 
-    pub fn into_vector(self) -> Vector {
+    pub fn into_vector(self) -> Vector<T> {
         Vector { x: self.x, y: self.y, z: self.z }
     }
+
+    // In the other precision
+    pub fn cast<U: Scalar>(self) -> Point<U> {
+        Point {
+            x: U::from_f64(self.x.to_f64()),
+            y: U::from_f64(self.y.to_f64()),
+            z: U::from_f64(self.z.to_f64()),
+        }
+    }
 }
 
-impl Add<Vector> for Point {
-    type Output = Point;
+impl<T: Scalar> Add<Vector<T>> for Point<T> {
+    type Output = Point<T>;
 
-    fn add(self, other: Vector) -> Point {
+    fn add(self, other: Vector<T>) -> Point<T> {
         Point {
             x: self.x + other.x,
             y: self.y + other.y,
@@ -36,10 +46,10 @@ impl Add<Vector> for Point {
     }
 }
 
-impl Sub<Vector> for Point {
-    type Output = Point;
+impl<T: Scalar> Sub<Vector<T>> for Point<T> {
+    type Output = Point<T>;
 
-    fn sub(self, other: Vector) -> Point {
+    fn sub(self, other: Vector<T>) -> Point<T> {
         Point {
             x: self.x - other.x,
             y: self.y - other.y,
@@ -48,10 +58,10 @@ impl Sub<Vector> for Point {
     }
 }
 
-impl Sub<Point> for Point {
-    type Output = Vector;
+impl<T: Scalar> Sub<Point<T>> for Point<T> {
+    type Output = Vector<T>;
 
-    fn sub(self, other: Point) -> Vector {
+    fn sub(self, other: Point<T>) -> Vector<T> {
         Vector {
             x: self.x - other.x,
             y: self.y - other.y,
@@ -0,0 +1,235 @@
+use crate::bsdf::{reflect, BSDFSample, Frame, BSDF};
+use crate::color::Color;
+use crate::float::consts::FRAC_1_PI;
+use crate::float::Float;
+use crate::microfacet::TrowbridgeReitz;
+use crate::sampling::cosine_hemisphere;
+use crate::vector::Vector;
+
+// Fixed glossy coating, the clearcoat slider only controls its strength
+const CLEARCOAT_ALPHA: Float = 0.05;
+const CLEARCOAT_F0: Float = 0.04;
+
+fn schlick_weight(cos: Float) -> Float {
+    (1. - cos.clamp(0., 1.)).powi(5)
+}
+
+fn schlick(f0: Color, cos: Float) -> Color {
+    let w = schlick_weight(cos);
+    f0 * (1. - w) + Color::new(w, w, w)
+}
+
+// Disney style layered BSDF: a clearcoat over a metallic/dielectric specular lobe
+// over a diffuse and sheen base, blended with rough glass for transmission.
+// The frame normal has to point to the outside for transmission.
+pub struct PrincipledBSDF<'a> {
+    pub frame: Frame,
+    pub base_color: Color,
+    pub metallic: Float,
+    pub specular: Float,
+    pub sheen: Float,
+    pub clearcoat: Float,
+    pub transmission: Float,
+    pub distribution: TrowbridgeReitz,
+    pub glass: Box<dyn BSDF + 'a>,
+}
+
+impl PrincipledBSDF<'_> {
+    fn glass_weight(&self) -> Float {
+        (1. - self.metallic) * self.transmission
+    }
+
+    // Sampling probabilities of the diffuse, specular, clearcoat and glass lobes
+    fn lobe_probabilities(&self) -> [Float; 4] {
+        let glass = self.glass_weight();
+        let diffuse = 1. - self.metallic;
+        let specular = 1.;
+        let clearcoat = 0.25 * self.clearcoat;
+        let opaque = (1. - glass) / (diffuse + specular + clearcoat);
+        [
+            opaque * diffuse,
+            opaque * specular,
+            opaque * clearcoat,
+            glass,
+        ]
+    }
+
+    fn specular_f0(&self) -> Color {
+        let dielectric = 0.08 * self.specular;
+        Color::new(dielectric, dielectric, dielectric) * (1. - self.metallic)
+            + self.base_color * self.metallic
+    }
+
+    fn coating() -> TrowbridgeReitz {
+        TrowbridgeReitz {
+            alpha_x: CLEARCOAT_ALPHA,
+            alpha_y: CLEARCOAT_ALPHA,
+        }
+    }
+
+    // Local directions, mirrored so wo lies in the upper hemisphere
+    fn local(&self, wo: Vector, wi: Vector) -> (Vector, Vector) {
+        let (wo, wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        if wo.z < 0. {
+            (-wo, -wi)
+        } else {
+            (wo, wi)
+        }
+    }
+
+    // Fraction of the energy passing through the clearcoat, in and out
+    fn coat_transmission(&self, cos_o: Float, cos_i: Float) -> Float {
+        let coat = |cos: Float| {
+            1. - self.clearcoat * (CLEARCOAT_F0 + (1. - CLEARCOAT_F0) * schlick_weight(cos))
+        };
+        coat(cos_o) * coat(cos_i)
+    }
+
+    // Fraction of the energy passing through the dielectric specular layer
+    fn specular_transmission(&self, cos_o: Float, cos_i: Float) -> Float {
+        let f0 = 0.08 * self.specular;
+        let layer = |cos: Float| 1. - (f0 + (1. - f0) * schlick_weight(cos));
+        layer(cos_o) * layer(cos_i)
+    }
+
+    // Local directions in the upper hemisphere
+    fn evaluate_opaque(&self, wo: Vector, wi: Vector) -> Color {
+        if wo.z <= 0. || wi.z <= 0. {
+            return Color::BLACK;
+        }
+
+        let wm = (wo + wi).normalize();
+        let cos_d = wi * wm;
+
+        // Burley diffuse with retro-reflection and sheen at grazing angles
+        let fd90 = 0.5 + 2. * self.distribution.alpha_x.sqrt() * cos_d * cos_d;
+        let fd =
+            (1. + (fd90 - 1.) * schlick_weight(wo.z)) * (1. + (fd90 - 1.) * schlick_weight(wi.z));
+        let sheen = self.sheen * schlick_weight(cos_d);
+        let base = (1. - self.metallic)
+            * self.specular_transmission(wo.z, wi.z)
+            * (FRAC_1_PI * fd * self.base_color + Color::new(sheen, sheen, sheen));
+
+        let specular = if self.distribution.is_smooth() {
+            Color::BLACK
+        } else {
+            schlick(self.specular_f0(), wo * wm)
+                * (self.distribution.d(wm) * self.distribution.g(wo, wi) / (4. * wo.z * wi.z))
+        };
+
+        let coating = Self::coating();
+        let clearcoat = self.clearcoat
+            * (CLEARCOAT_F0 + (1. - CLEARCOAT_F0) * schlick_weight(wo * wm))
+            * coating.d(wm)
+            * coating.g(wo, wi)
+            / (4. * wo.z * wi.z);
+
+        self.coat_transmission(wo.z, wi.z) * (base + specular)
+            + Color::new(clearcoat, clearcoat, clearcoat)
+    }
+
+    fn pdf_opaque(&self, wo: Vector, wi: Vector, probabilities: [Float; 4]) -> Float {
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+
+        let wm = (wo + wi).normalize();
+        let reflection = (4. * (wo * wm).abs()).recip();
+        let specular = if self.distribution.is_smooth() {
+            0.
+        } else {
+            self.distribution.pdf(wo, wm) * reflection
+        };
+        let clearcoat = Self::coating().pdf(wo, wm) * reflection;
+
+        probabilities[0] * wi.z * FRAC_1_PI
+            + probabilities[1] * specular
+            + probabilities[2] * clearcoat
+    }
+}
+
+impl BSDF for PrincipledBSDF<'_> {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Color {
+        let (lo, li) = self.local(wo, wi);
+        let glass = self.glass_weight();
+        (1. - glass) * self.evaluate_opaque(lo, li)
+            + glass * self.base_color * self.glass.evaluate(wo, wi)
+    }
+
+    fn sample(&self, wo: Vector, u: (Float, Float)) -> Option<BSDFSample> {
+        let probabilities = self.lobe_probabilities();
+
+        // Pick a lobe and reuse the remainder of u.0
+        let mut lobe = 0;
+        let mut u0 = u.0;
+        while lobe < 3 && u0 >= probabilities[lobe] {
+            u0 -= probabilities[lobe];
+            lobe += 1;
+        }
+        if probabilities[lobe] == 0. {
+            return None;
+        }
+        let u = ((u0 / probabilities[lobe]).min(1. - Float::EPSILON), u.1);
+
+        if lobe == 3 {
+            let sample = self.glass.sample(wo, u)?;
+            if sample.specular {
+                return Some(BSDFSample {
+                    value: self.glass_weight() * self.base_color * sample.value,
+                    pdf: probabilities[3] * sample.pdf,
+                    ..sample
+                });
+            }
+            return Some(BSDFSample {
+                value: self.evaluate(wo, sample.direction),
+                pdf: self.pdf(wo, sample.direction),
+                ..sample
+            });
+        }
+
+        let lo = self.frame.to_local(wo);
+        let flip = lo.z < 0.;
+        let lo = if flip { -lo } else { lo };
+        if lo.z == 0. {
+            return None;
+        }
+        let to_world = |w: Vector| self.frame.to_world(if flip { -w } else { w });
+
+        let li = match lobe {
+            0 => cosine_hemisphere(u),
+            1 if self.distribution.is_smooth() => {
+                // Mirror-like specular, can't be combined with the other lobes
+                let li = Vector::new(-lo.x, -lo.y, lo.z);
+                let value = (1. - self.glass_weight())
+                    * self.coat_transmission(lo.z, li.z)
+                    * schlick(self.specular_f0(), lo.z)
+                    * li.z.recip();
+                return Some(BSDFSample {
+                    direction: to_world(li),
+                    value,
+                    pdf: probabilities[1],
+                    specular: true,
+                });
+            }
+            1 => reflect(lo, self.distribution.sample_wm(lo, u)),
+            _ => reflect(lo, Self::coating().sample_wm(lo, u)),
+        };
+        if li.z <= 0. {
+            return None;
+        }
+
+        let direction = to_world(li);
+        Some(BSDFSample {
+            direction,
+            value: self.evaluate(wo, direction),
+            pdf: self.pdf(wo, direction),
+            specular: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> Float {
+        let probabilities = self.lobe_probabilities();
+        let (lo, li) = self.local(wo, wi);
+        self.pdf_opaque(lo, li, probabilities) + probabilities[3] * self.glass.pdf(wo, wi)
+    }
+}
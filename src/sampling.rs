@@ -0,0 +1,241 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::vector::Vector;
+
+// Every thread gets its own generator, seeded from a shared counter
+static SEED: AtomicU64 = AtomicU64::new(0x853c_49e6_748f_ea9b);
+
+thread_local! {
+    static STATE: Cell<u64> =
+        Cell::new(splitmix64(SEED.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)));
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) | 1
+}
+
+// Uniform on [0, 1), xorshift64*
+pub fn random() -> Float {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as Float * (1. / (1u64 << 53) as Float)
+    })
+}
+
+// Uniform on [0, 1) × [0, 1)
+pub fn random2() -> (Float, Float) {
+    (random(), random())
+}
+
+// On the unit disk, preserves stratification
+pub fn concentric_disk(u: (Float, Float)) -> (Float, Float) {
+    let (a, b) = (2. * u.0 - 1., 2. * u.1 - 1.);
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4. * (b / a))
+    } else {
+        (b, PI / 2. - PI / 4. * (a / b))
+    };
+    let (s, c) = theta.sin_cos();
+    (r * c, r * s)
+}
+
+pub fn uniform_sphere(u: (Float, Float)) -> Vector {
+    let z = 1. - 2. * u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let (s, c) = (2. * PI * u.1).sin_cos();
+    Vector::new(r * c, r * s, z)
+}
+
+// Around +z, pdf is cos(theta) / pi
+pub fn cosine_hemisphere(u: (Float, Float)) -> Vector {
+    let (x, y) = concentric_disk(u);
+    Vector::new(x, y, (1. - x * x - y * y).max(0.).sqrt())
+}
+
+// Around +z within the cone of directions with cos(theta) >= cos_max, pdf is
+// 1 / (2 pi (1 - cos_max))
+pub fn uniform_cone(u: (Float, Float), cos_max: Float) -> Vector {
+    let z = 1. - u.0 * (1. - cos_max);
+    let r = (1. - z * z).max(0.).sqrt();
+    let (s, c) = (2. * PI * u.1).sin_cos();
+    Vector::new(r * c, r * s, z)
+}
+
+// Piecewise constant over [0, 1) with one bin per weight
+pub struct Distribution1D {
+    cdf: Vec<Float>,
+    total: Float,
+}
+
+impl Distribution1D {
+    // Falls back to uniform if all weights are zero
+    pub fn new(weights: &[Float]) -> Distribution1D {
+        let total: Float = weights.iter().sum();
+        let n = weights.len() as Float;
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut sum = 0.;
+        cdf.push(0.);
+        for (i, weight) in weights.iter().enumerate() {
+            sum += weight;
+            cdf.push(if total > 0. {
+                sum / total
+            } else {
+                (i + 1) as Float / n
+            });
+        }
+        Distribution1D { cdf, total }
+    }
+
+    pub fn total(&self) -> Float {
+        self.total
+    }
+
+    fn bins(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    // Position, its pdf and the bin it is in
+    pub fn sample(&self, u: Float) -> (Float, Float, usize) {
+        // Last bin with cdf[i] <= u, skipping empty bins
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.bins() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let t = if width > 0. {
+            (u - self.cdf[i]) / width
+        } else {
+            0.
+        };
+        let n = self.bins() as Float;
+        ((i as Float + t) / n, width * n, i)
+    }
+
+    pub fn pdf(&self, x: Float) -> Float {
+        let n = self.bins();
+        let i = ((x * n as Float) as usize).min(n - 1);
+        (self.cdf[i + 1] - self.cdf[i]) * n as Float
+    }
+
+    // Bin chosen with probability proportional to its weight
+    pub fn sample_discrete(&self, u: Float) -> (usize, Float) {
+        let (_, _, i) = self.sample(u);
+        (i, self.pmf(i))
+    }
+
+    pub fn pmf(&self, i: usize) -> Float {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+}
+
+// Piecewise constant over [0, 1) × [0, 1), the weights are row-major
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(width: usize, height: usize, weights: &[Float]) -> Distribution2D {
+        assert_eq!(weights.len(), width * height);
+        let conditional: Vec<_> = weights.chunks(width).map(Distribution1D::new).collect();
+        let marginal: Vec<_> = conditional.iter().map(|row| row.total()).collect();
+        Distribution2D {
+            conditional,
+            marginal: Distribution1D::new(&marginal),
+        }
+    }
+
+    // Sample (x, y) and its pdf, y selects the row
+    pub fn sample(&self, u: (Float, Float)) -> ((Float, Float), Float) {
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, (x, y): (Float, Float)) -> Float {
+        let rows = self.conditional.len();
+        let row = ((y * rows as Float) as usize).min(rows - 1);
+        self.marginal.pdf(y) * self.conditional[row].pdf(x)
+    }
+}
+
+// Weight of a sample from the strategy with pdf f against one with pdf g
+pub fn power_heuristic(f: Float, g: Float) -> Float {
+    if f.is_infinite() {
+        return 1.;
+    }
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 > 0. {
+        f2 / (f2 + g2)
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stratified(n: usize) -> impl Iterator<Item = Float> {
+        (0..n).map(move |i| (i as Float + 0.5) / n as Float)
+    }
+
+    #[test]
+    fn distribution_1d_pdf() {
+        let distribution = Distribution1D::new(&[1., 3., 0., 4.]);
+        assert_eq!(distribution.total(), 8.);
+
+        // The pdf integrates to one and matches that of the samples
+        let integral = stratified(1000).map(|x| distribution.pdf(x)).sum::<Float>() / 1000.;
+        assert!((integral - 1.).abs() < 1e-4);
+        for u in stratified(1000) {
+            let (x, pdf, i) = distribution.sample(u);
+            assert!(i != 2, "sampled an empty bin");
+            assert!((pdf - distribution.pdf(x)).abs() < 1e-4);
+        }
+
+        let (i, pmf) = distribution.sample_discrete(0.2);
+        assert_eq!(i, 1);
+        assert!((pmf - 3. / 8.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn distribution_1d_uniform_without_weights() {
+        let distribution = Distribution1D::new(&[0., 0.]);
+        assert_eq!(distribution.pdf(0.3), 1.);
+        assert_eq!(distribution.sample(0.75).0, 0.75);
+    }
+
+    #[test]
+    fn distribution_2d_pdf() {
+        let weights = [1., 2., 0., 5., 3., 1.];
+        let distribution = Distribution2D::new(3, 2, &weights);
+
+        let mut integral = 0.;
+        for y in stratified(60) {
+            for x in stratified(60) {
+                integral += distribution.pdf((x, y));
+            }
+        }
+        integral /= 3600.;
+        assert!((integral - 1.).abs() < 1e-4);
+
+        // The weight over the mean weight
+        assert!((distribution.pdf((0.5, 0.25)) - 2. / 2.).abs() < 1e-4);
+        for u in stratified(1000) {
+            let (point, pdf) = distribution.sample((u, (7. * u).fract()));
+            assert!((pdf - distribution.pdf(point)).abs() < 1e-3);
+        }
+    }
+}
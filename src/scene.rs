@@ -1,56 +1,233 @@
+use crate::camera;
 use crate::color::Color;
+use crate::float::Float;
+use crate::geometries::Hit;
+use crate::integrator::{emission_weight, offset, scattered_light, Integrator};
 use crate::light::Light;
 use crate::materials::Background;
+use crate::medium::Medium;
 use crate::objects::Object;
 use crate::point::Point;
 use crate::ray::Ray;
+use crate::sampling::random2;
+
+// Relative distance, for points on surfaces
+pub(crate) const SHADOW_EPSILON: Float = 1e-4;
+// Shadow rays through more transparent surfaces count as blocked
+const MAX_SHADOW_SURFACES: usize = 16;
 
 pub struct Scene {
     pub max_bounces: u32,
     pub objects: Vec<Object>,
     pub lights: Vec<Box<dyn Light>>,
     pub background: Box<dyn Background>,
+    // Fills the space outside of the objects
+    pub atmosphere: Option<Box<dyn Medium>>,
+    pub integrator: Box<dyn Integrator>,
 }
 
 impl Scene {
-    pub fn trace(&self, ray: Ray) -> Option<(f64, &Object, Point)> {
-        self.objects
+    pub fn trace(&self, ray: Ray) -> Option<(Float, &Object, Hit)> {
+        self.trace_indexed(ray)
+            .map(|(distance, index, hit)| (distance, &self.objects[index], hit))
+    }
+
+    // Like trace, with the index of the object in objects
+    pub fn trace_indexed(&self, ray: Ray) -> Option<(Float, usize, Hit)> {
+        let (distance, index, part) = self
+            .objects
             .iter()
-            .filter_map(|object| {
+            .enumerate()
+            .filter_map(|(index, object)| {
                 object
                     .geometry
+                    .intersect_part(ray)
+                    .map(|(distance, part)| (distance, index, part))
+            })
+            .min_by(|inter1, inter2| inter1.0.partial_cmp(&inter2.0).unwrap())?;
+        let point = ray.origin + distance * ray.direction;
+        let mut hit = self.objects[index].geometry.part_hit(point, part);
+        // Stretched over surfaces seen at a grazing angle
+        let footprint = camera::pixel_footprint(point);
+        if footprint > 0. {
+            hit.footprint = footprint / (ray.direction.normalize() * hit.normal).abs();
+        }
+        Some((distance, index, hit))
+    }
+
+    // Explicit lights followed by the objects with an emissive material and
+    // the background if it can be sampled. Looking for the emitters costs less
+    // than tracing a ray through the objects.
+    pub fn light_sources(&self) -> impl Iterator<Item = &dyn Light> + '_ {
+        self.lights
+            .iter()
+            .map(|light| light.as_ref())
+            .chain(
+                self.objects
+                    .iter()
+                    .filter(|object| object.material.is_emissive())
+                    .map(|object| object as &dyn Light),
+            )
+            .chain(self.background.light())
+    }
+
+    // Nearest light surface hit by the ray, lights don't cast shadows
+    pub fn trace_lights(&self, ray: Ray) -> Option<(Float, Color, &dyn Light)> {
+        self.lights
+            .iter()
+            .filter_map(|light| {
+                light
                     .intersect(ray)
-                    .map(|distance| (distance, object, ray.origin + distance * ray.direction))
+                    .map(|(distance, radiance)| (distance, radiance, light.as_ref()))
             })
-            .min_by(|inter1, inter2| inter1.0.partial_cmp(&inter2.0).unwrap())
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
     }
 
-    pub fn see(&self, a: Point, b: Point) -> bool {
-        let ray = Ray {
-            origin: a,
-            direction: (b - a).normalize(),
-        };
-        if let Some(inter) = self.trace(ray) {
-            inter.0 > (b - a).norm()
-        } else {
-            true
+    // Medium the ray travels through up to its intersection, the object's or its
+    // material's when leaving it and the atmosphere otherwise. Nested media
+    // aren't supported: only the next hit is looked at, so a ray inside an
+    // object's medium heading to an object within it gets the atmosphere.
+    pub fn medium<'a>(
+        &'a self,
+        ray: Ray,
+        inter: Option<(Float, &'a Object, Hit)>,
+    ) -> Option<Box<dyn Medium + 'a>> {
+        match inter {
+            Some((_, object, hit)) if ray.direction * hit.normal > 0. => {
+                match object.medium.as_deref() {
+                    Some(medium) => Some(Box::new(medium)),
+                    None => object.material.medium(&hit),
+                }
+            }
+            _ => self
+                .atmosphere
+                .as_deref()
+                .map(|medium| Box::new(medium) as Box<dyn Medium>),
         }
     }
 
+    // Fraction of light from b reaching a, passing through transparent surfaces
+    pub fn see(&self, a: Point, b: Point) -> Color {
+        self.transmittance(a, b, 0.)
+    }
+
+    // Like see for points b on surfaces, like those sampled on emitters, which
+    // don't occlude themselves
+    pub fn see_surface(&self, a: Point, b: Point) -> Color {
+        self.transmittance(a, b, SHADOW_EPSILON)
+    }
+
+    // Surfaces within the relative epsilon of b don't block
+    fn transmittance(&self, a: Point, b: Point, epsilon: Float) -> Color {
+        let direction = (b - a).normalize();
+        let ignore_refraction = self.integrator.ignores_refraction();
+        let mut transmittance = Color::new(1., 1., 1.);
+        let mut origin = a;
+        for _ in 0..MAX_SHADOW_SURFACES {
+            let ray = Ray { origin, direction };
+            let inter = self.trace(ray);
+            let remaining = (b - origin).norm();
+            let blocking = inter.filter(|inter| inter.0 <= (1. - epsilon) * remaining);
+            if let Some(medium) = self.medium(ray, inter) {
+                let length = blocking.map_or(remaining, |inter| inter.0);
+                transmittance *= medium.transmittance(ray, length);
+            }
+            let (distance, object, hit) = match blocking {
+                Some(inter) => inter,
+                None => return transmittance,
+            };
+
+            transmittance *=
+                object
+                    .material
+                    .shadow_transmittance(&hit, direction, ignore_refraction);
+            // Leaving the object, the ray travelled through its interior
+            if direction * hit.normal > 0. {
+                transmittance *= object.material.interior_transmittance(&hit, distance);
+            }
+            if transmittance.is_black() {
+                return Color::BLACK;
+            }
+            origin = offset(hit.point, hit.normal, direction);
+        }
+        Color::BLACK
+    }
+
+    pub fn radiance(&self, ray: Ray) -> Color {
+        self.integrator.radiance(self, ray)
+    }
+
+    // For rays already traced, see Integrator::radiance_traced
+    pub fn radiance_traced(&self, ray: Ray, inter: Option<(Float, &Object, Hit)>) -> Color {
+        self.integrator.radiance_traced(self, ray, inter)
+    }
+
     pub fn color(&self, ray: Ray, bounces: u32) -> Color {
+        self.sampled_color(ray, bounces, None)
+    }
+
+    // Color along a ray sampled from a BSDF with the given pdf, None for specular
+    // directions. Emission is weighted against integrator::direct_lighting.
+    pub fn sampled_color(&self, ray: Ray, bounces: u32, bsdf_pdf: Option<Float>) -> Color {
+        let background = || {
+            let weight = emission_weight(self.background.light(), ray, bsdf_pdf);
+            self.background.background_color(self, ray) * weight
+        };
         if bounces > self.max_bounces {
-            self.background.background_color(self, ray)
-        } else {
-            match self.trace(ray) {
-                Some(inter) => inter.1.material.surface_color(
-                    self,
-                    inter.2,
-                    inter.1.geometry.surface_normal(inter.2),
-                    ray,
-                    bounces,
-                ),
-                None => self.background.background_color(self, ray),
+            return background();
+        }
+
+        let inter = self.trace(ray);
+        let light = self
+            .trace_lights(ray)
+            .filter(|light| inter.is_none_or(|inter| light.0 < inter.0));
+
+        // Inside a medium, light is sampled at scattering events and the path
+        // continues in a direction sampled from the phase function
+        let mut medium_weight = Color::new(1., 1., 1.);
+        if let Some(medium) = self.medium(ray, inter) {
+            let distance = light.map_or(inter.map_or(Float::INFINITY, |inter| inter.0), |light| {
+                light.0
+            });
+            let (scatter, weight) = medium.sample(ray, distance, Color::new(1., 1., 1.));
+            if let Some(t) = scatter {
+                let point = ray.origin + t * ray.direction;
+                let phase = medium.phase();
+                let wo = -ray.direction;
+                let wi = phase.sample(wo, random2());
+                let scattered = Ray {
+                    origin: point,
+                    direction: wi,
+                };
+                let pdf = phase.evaluate(wo, wi);
+                return weight
+                    * (scattered_light(self, &phase, point, wo)
+                        + self.sampled_color(scattered, bounces + 1, Some(pdf)));
             }
+            medium_weight = weight;
+        }
+
+        if let Some((_, radiance, light)) = light {
+            return medium_weight * radiance * emission_weight(Some(light), ray, bsdf_pdf);
         }
+
+        let color = match inter {
+            Some((distance, object, hit)) => {
+                let mut color = object.material.surface_color(self, &hit, ray, bounces);
+                if object.material.is_emissive() {
+                    let weight = emission_weight(Some(object), ray, bsdf_pdf);
+                    color += (weight - 1.) * object.material.emitted(&hit, ray);
+                }
+
+                // Leaving the object, the ray travelled through its interior
+                if ray.direction * hit.normal > 0. {
+                    color * object.material.interior_transmittance(&hit, distance)
+                } else {
+                    color
+                }
+            }
+            None => background(),
+        };
+        medium_weight * color
     }
 }
@@ -1,17 +1,21 @@
+use crate::float::{Float, Scalar};
 use crate::geometries::Geometry;
+use crate::noise::Noise;
 use crate::point::Point;
 use crate::ray::Ray;
 use crate::vector::Vector;
 
+// Evaluated in double precision also with the f32 feature, fractals need it to
+// resolve their detail
 pub trait SDF: Sync {
-    fn distance(&self, point: Point) -> f64;
+    fn distance(&self, point: Point<f64>) -> f64;
 
-    fn gradient(&self, point: Point) -> Vector {
+    fn gradient(&self, point: Point<f64>) -> Vector<f64> {
         // Estimate gradient using tetrahedron technique
-        let pmm: Vector = Vector::I - Vector::J - Vector::K;
-        let mmp: Vector = -Vector::I - Vector::J + Vector::K;
-        let mpm: Vector = -Vector::I + Vector::J - Vector::K;
-        let ppp: Vector = Vector::I + Vector::J + Vector::K;
+        let pmm: Vector<f64> = Vector::I - Vector::J - Vector::K;
+        let mmp: Vector<f64> = -Vector::I - Vector::J + Vector::K;
+        let mpm: Vector<f64> = -Vector::I + Vector::J - Vector::K;
+        let ppp: Vector<f64> = Vector::I + Vector::J + Vector::K;
 
         const EPS: f64 = 10e-8;
 
@@ -22,32 +26,32 @@ pub trait SDF: Sync {
     }
 }
 
-impl SDF for fn(Point) -> (f64, Vector) {
-    fn distance(&self, point: Point) -> f64 {
+impl SDF for fn(Point<f64>) -> (f64, Vector<f64>) {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self(point).0
     }
 
-    fn gradient(&self, point: Point) -> Vector {
+    fn gradient(&self, point: Point<f64>) -> Vector<f64> {
         self(point).1
     }
 }
 
-impl SDF for fn(Point) -> f64 {
-    fn distance(&self, point: Point) -> f64 {
+impl SDF for fn(Point<f64>) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self(point)
     }
 }
 
-/// Primitives
+// Primitives
 
 pub struct SDFSphere {}
 
 impl SDF for SDFSphere {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         (point - Point::ORIGIN).norm() - 1.
     }
 
-    fn gradient(&self, point: Point) -> Vector {
+    fn gradient(&self, point: Point<f64>) -> Vector<f64> {
         (point - Point::ORIGIN).normalize()
     }
 }
@@ -59,7 +63,7 @@ pub struct SDFBox {
 }
 
 impl SDF for SDFBox {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         let q = point.into_vector().abs()
             - Vector {
                 x: self.lengthx / 2.,
@@ -73,7 +77,7 @@ impl SDF for SDFBox {
 pub struct SDFCube {}
 
 impl SDF for SDFCube {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         let q = point.into_vector().abs() - 0.5;
         q.max(0.).norm().min(q.maxcomp())
     }
@@ -82,7 +86,7 @@ impl SDF for SDFCube {
 pub struct SDFCross {}
 
 impl SDF for SDFCross {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         let da = point.x.abs().max(point.y.abs());
         let db = point.y.abs().max(point.z.abs());
         let dc = point.z.abs().max(point.x.abs());
@@ -98,7 +102,7 @@ pub struct SDFUnion {
 }
 
 impl SDF for SDFUnion {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self.sdfa.distance(point).min(self.sdfb.distance(point))
     }
 }
@@ -109,7 +113,7 @@ pub struct SDFIntersect {
 }
 
 impl SDF for SDFIntersect {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self.sdfa.distance(point).max(self.sdfb.distance(point))
     }
 }
@@ -120,28 +124,44 @@ pub struct SDFSubtract {
 }
 
 impl SDF for SDFSubtract {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self.sdfa.distance(point).max(-self.sdfb.distance(point))
     }
 }
 
+// Offsets the surface by noise, the result is no longer an exact distance so
+// keep the amplitude small compared to the scale or lower the marcher's tolerance
+pub struct SDFDisplace {
+    pub sdf: Box<dyn SDF>,
+    pub noise: Box<dyn Noise>,
+    pub amplitude: f64,
+    pub scale: f64,
+}
+
+impl SDF for SDFDisplace {
+    fn distance(&self, point: Point<f64>) -> f64 {
+        let p = Point::ORIGIN + point.into_vector() * self.scale.recip();
+        self.sdf.distance(point) + self.amplitude * self.noise.noise(p.cast()).to_f64()
+    }
+}
+
 trait SDFTransform {
     fn get_sdf(&self) -> &dyn SDF;
     fn transform(&self, scalar: f64) -> f64;
-    fn inverse_transform(&self, point: Point) -> Point;
+    fn inverse_transform(&self, point: Point<f64>) -> Point<f64>;
 }
 
 impl<T: Sync> SDF for T
 where
     T: SDFTransform,
 {
-    fn distance(&self, point: Point) -> f64 {
+    fn distance(&self, point: Point<f64>) -> f64 {
         self.transform(self.get_sdf().distance(self.inverse_transform(point)))
     }
 }
 pub struct SDFTranslate {
     pub sdf: Box<dyn SDF>,
-    pub shift: Vector,
+    pub shift: Vector<f64>,
 }
 
 impl SDFTransform for SDFTranslate {
@@ -153,7 +173,7 @@ impl SDFTransform for SDFTranslate {
         scalar
     }
 
-    fn inverse_transform(&self, point: Point) -> Point {
+    fn inverse_transform(&self, point: Point<f64>) -> Point<f64> {
         point - self.shift
     }
 }
@@ -163,7 +183,7 @@ pub struct SDFRotate {
     pub pitch: f64,
     pub yaw: f64,
     pub roll: f64,
-    pub reference: Point,
+    pub reference: Point<f64>,
 }
 
 impl SDFTransform for SDFRotate {
@@ -175,7 +195,7 @@ impl SDFTransform for SDFRotate {
         scalar
     }
 
-    fn inverse_transform(&self, point: Point) -> Point {
+    fn inverse_transform(&self, point: Point<f64>) -> Point<f64> {
         self.reference
             + (point - self.reference)
                 .rotate_about_x_axis(-self.pitch.to_radians())
@@ -197,7 +217,7 @@ impl SDFTransform for SDFScale {
         self.scale * scalar
     }
 
-    fn inverse_transform(&self, point: Point) -> Point {
+    fn inverse_transform(&self, point: Point<f64>) -> Point<f64> {
         Point {
             x: point.x / self.scale,
             y: point.y / self.scale,
@@ -208,6 +228,9 @@ impl SDFTransform for SDFScale {
 
 // Rendering
 
+// Steps refining the distance to hits
+const MAX_REFINEMENTS: u32 = 16;
+
 pub struct SDFMarcher {
     pub sdf: Box<dyn SDF>,
     pub max_iterations: u32,
@@ -217,37 +240,134 @@ pub struct SDFMarcher {
 
 impl Geometry for SDFMarcher
 {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
+    fn intersect(&self, ray: Ray) -> Option<Float> {
+        let (origin, direction) = (ray.origin.cast::<f64>(), ray.direction.cast::<f64>());
+        // Rays starting inside march towards the surface from the inside
+        let sign = if self.sdf.distance(origin) < 0. {
+            -1.
+        } else {
+            1.
+        };
+        // Positive on the side of the origin
+        let distance = |t: f64| sign * self.sdf.distance(origin + t * direction);
+
         // Ray marching
         let mut t = 0.;
+        let mut last = 0.;
+        let mut d = distance(t);
         for _ in 0..self.max_iterations {
-            let d = self.sdf.distance(ray.origin + t * ray.direction);
-
-            if d.abs() < self.tolerance * t {
+            if d < self.tolerance * t {
                 break;
             }
-
+            last = t;
             t += 0.95 * d;
-
             if t > self.max_distance {
-                t = 0.0;
+                return None;
+            }
+            d = distance(t);
+        }
+        if t == 0. {
+            return None;
+        }
+
+        // Refine the crossing so hit points lie on the side the ray came from, close
+        // enough to the surface for rays offset from them to start on the other side
+        let ((mut near, mut d_near), (mut far, mut d_far)) = if d > 0. {
+            let step = 2. * d.max(self.tolerance * t);
+            match (1..=4)
+                .map(|i| t + i as f64 * step)
+                .map(|far| (far, distance(far)))
+                .find(|&(_, d_far)| d_far <= 0.)
+            {
+                Some(far) => ((t, d), far),
+                // Grazing the surface
+                None => ((t, 0.), (t, 0.)),
+            }
+        } else {
+            ((last, distance(last)), (t, d))
+        };
+        // False position, halving the value at an end which stays put twice in a
+        // row (Illinois) so the bracket keeps shrinking
+        let mut moved = 0;
+        for _ in 0..MAX_REFINEMENTS {
+            // Exactly on the surface
+            if d_far == 0. {
+                near = far;
+            }
+            if far - near <= f64::EPSILON * far.max(1.) {
                 break;
             }
+            let mut middle = near + (far - near) * d_near / (d_near - d_far);
+            if !(middle > near && middle < far) {
+                middle = (near + far) / 2.;
+            }
+            let d_middle = distance(middle);
+            if d_middle > 0. {
+                (near, d_near) = (middle, d_middle);
+                if moved == -1 {
+                    d_far /= 2.;
+                }
+                moved = -1;
+            } else {
+                (far, d_far) = (middle, d_middle);
+                if moved == 1 {
+                    d_near /= 2.;
+                }
+                moved = 1;
+            }
         }
+        let t = near.max(f64::MIN_POSITIVE);
 
-        if t > 0.0
-            && self
-                .surface_normal(ray.origin + t * ray.direction)
-                .dot(ray.direction)
-                < 1e-8
-        {
-            Some(t)
+        let normal = self.sdf.gradient(origin + t * direction).normalize();
+        if sign * normal.dot(direction) < 1e-8 {
+            Some(Float::from_f64(t))
         } else {
             None
         }
     }
 
     fn surface_normal(&self, point: Point) -> Vector {
-        self.sdf.gradient(point).normalize()
+        self.sdf.gradient(point.cast()).normalize().cast()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::offset;
+
+    #[test]
+    fn refracted_rays_leave_the_surface() {
+        let sphere = SDFMarcher {
+            sdf: Box::new(SDFSphere {}),
+            max_iterations: 128,
+            max_distance: 20.,
+            tolerance: 1e-5,
+        };
+        for direction in [Vector::new(0., 0., -1.), Vector::new(0.28, 0., -0.96)] {
+            let ray = Ray {
+                origin: Point::new(0., 0., 3.),
+                direction,
+            };
+            let t = sphere.intersect(ray).unwrap();
+            let point = ray.origin + t * ray.direction;
+            assert!(SDFSphere {}.distance(point.cast()).abs() < 1e-6);
+
+            // Going through the surface finds the other side of the sphere
+            let normal = sphere.surface_normal(point);
+            let inside = Ray {
+                origin: offset(point, normal, -normal),
+                direction: -normal,
+            };
+            let through = sphere.intersect(inside).unwrap();
+            assert!((through - 2.).abs() < 1e-4);
+
+            // Leaving it finds nothing
+            let outside = Ray {
+                origin: offset(point, normal, normal),
+                direction: normal,
+            };
+            assert!(sphere.intersect(outside).is_none());
+        }
     }
 }
@@ -0,0 +1,181 @@
+use crate::color::Color;
+use crate::float::consts::{FRAC_PI_2, PI};
+use crate::float::Float;
+use crate::light::DirectionalLight;
+use crate::materials::Background;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vector::Vector;
+
+// Blends from the horizon to the zenith color with height, uniform below
+pub struct GradientSky {
+    pub zenith: Color,
+    pub horizon: Color,
+    pub ground: Color,
+}
+
+impl Background for GradientSky {
+    fn background_color(&self, _scene: &Scene, ray: Ray) -> Color {
+        let height = ray.direction.normalize().y;
+        if height < 0. {
+            self.ground
+        } else {
+            (1. - height) * self.horizon + height * self.zenith
+        }
+    }
+}
+
+// Linear sRGB from CIE xyY
+fn xyy_color(x: Float, y: Float, luminance: Float) -> Color {
+    if y <= 0. {
+        return Color::BLACK;
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1. - x - y) / y * luminance;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+}
+
+// Perez sky luminance distribution
+fn perez([a, b, c, d, e]: [Float; 5], cos_theta: Float, gamma: Float) -> Float {
+    (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// Analytic daylight, see Preetham et al. 1999. The sky is clear for a turbidity
+// of about 2 and hazy above 6. Luminance is in kcd/m² times the intensity.
+pub struct PreethamSky {
+    // Towards the sun
+    pub sun_direction: Vector,
+    pub turbidity: Float,
+    pub intensity: Float,
+    // Multiplies the horizon below it
+    pub ground: Color,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vector, turbidity: Float) -> PreethamSky {
+        PreethamSky {
+            sun_direction,
+            turbidity,
+            intensity: 0.1,
+            ground: Color::new(0.3, 0.3, 0.3),
+        }
+    }
+
+    // Zenith angle of the sun, just above the horizon at most
+    fn sun_theta(&self) -> Float {
+        let cos = self.sun_direction.normalize().y.clamp(0., 1.);
+        cos.acos().min(FRAC_PI_2 - 1e-3)
+    }
+
+    fn coefficients(&self) -> [[Float; 5]; 3] {
+        let t = self.turbidity;
+        [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ]
+    }
+
+    // Luminance and chromaticity at the zenith
+    fn zenith(&self) -> [Float; 3] {
+        let (t, theta) = (self.turbidity, self.sun_theta());
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let cubic =
+            |c: [Float; 4]| c[0] * theta.powi(3) + c[1] * theta.powi(2) + c[2] * theta + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        [luminance, x, y]
+    }
+
+    pub fn radiance(&self, direction: Vector) -> Color {
+        let direction = direction.normalize();
+        let below = direction.y < 0.;
+        // Just above the horizon to keep the distribution finite
+        let cos_theta = direction.y.abs().max(1e-3);
+        let sun = self.sun_direction.normalize();
+        let horizontal = Vector::new(direction.x, 0., direction.z);
+        let theta_sun = self.sun_theta();
+        let gamma = if !below {
+            (direction * sun).clamp(-1., 1.).acos()
+        } else if horizontal.norm_squared() > 0. {
+            (horizontal.normalize() * sun).clamp(-1., 1.).acos()
+        } else {
+            // Straight down, as seen from the zenith
+            theta_sun
+        };
+
+        let coefficients = self.coefficients();
+        let zenith = self.zenith();
+        let value = |i: usize| {
+            zenith[i] * perez(coefficients[i], if below { 1e-3 } else { cos_theta }, gamma)
+                / perez(coefficients[i], 1., theta_sun)
+        };
+
+        let color = self.intensity * xyy_color(value(1), value(2), value(0));
+        let color = Color::new(color.red.max(0.), color.green.max(0.), color.blue.max(0.));
+        if below {
+            color * self.ground
+        } else {
+            color
+        }
+    }
+
+    // Sun attenuated by Rayleigh and aerosol scattering for the current
+    // position and turbidity, the irradiance is that outside the atmosphere
+    pub fn sun_light(&self, irradiance: Float, angular_diameter: Float) -> DirectionalLight {
+        let theta = self.sun_theta();
+        let mass = (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253)).recip();
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Representative wavelengths of the channels in micrometers
+        let transmittance = |lambda: Float| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * mass;
+            let aerosol = -beta * lambda.powf(-1.3) * mass;
+            (rayleigh + aerosol).exp()
+        };
+
+        DirectionalLight {
+            direction: -self.sun_direction.normalize(),
+            color: irradiance
+                * Color::new(
+                    transmittance(0.68),
+                    transmittance(0.55),
+                    transmittance(0.44),
+                ),
+            angular_diameter,
+        }
+    }
+}
+
+impl Background for PreethamSky {
+    fn background_color(&self, _scene: &Scene, ray: Ray) -> Color {
+        self.radiance(ray.direction)
+    }
+}
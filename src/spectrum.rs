@@ -0,0 +1,467 @@
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+
+pub const LAMBDA_MIN: Float = 360.;
+pub const LAMBDA_MAX: Float = 830.;
+
+// Traced together in the channels of a Color, the first one is the hero
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelengths {
+    // In nm
+    pub lambda: [Float; 3],
+    pub pdf: [Float; 3],
+}
+
+impl Wavelengths {
+    // Favors the visible wavelengths, see pbrt-v4. The others are rotated by a
+    // third of the range of u.
+    pub fn sample(u: Float) -> Wavelengths {
+        let mut lambda = [0.; 3];
+        let mut pdf = [0.; 3];
+        for i in 0..3 {
+            let u = (u + i as Float / 3.).fract();
+            lambda[i] = 538. - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Wavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> Float {
+        self.lambda[0]
+    }
+
+    // Once the path depends on the wavelength only the hero one is kept, its
+    // estimate then stands for all of them
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0. && self.pdf[2] == 0. {
+            return;
+        }
+        self.pdf = [self.pdf[0] / 3., 0., 0.];
+    }
+
+    fn map(&self, f: impl Fn(Float) -> Float) -> Color {
+        Color::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+}
+
+fn visible_pdf(lambda: Float) -> Float {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+    let cosh = (0.0072 * (lambda - 538.)).cosh();
+    0.0039398042 / (cosh * cosh)
+}
+
+thread_local! {
+    static WAVELENGTHS: Cell<Option<Wavelengths>> = const { Cell::new(None) };
+}
+
+// Wavelengths of the path being traced, None when rendering in RGB
+pub fn wavelengths() -> Option<Wavelengths> {
+    WAVELENGTHS.with(|wavelengths| wavelengths.get())
+}
+
+// Result of tracing at the wavelengths, and the wavelengths left afterwards
+pub fn with_wavelengths<T>(wavelengths: Wavelengths, f: impl FnOnce() -> T) -> (T, Wavelengths) {
+    let previous = WAVELENGTHS.with(|current| current.replace(Some(wavelengths)));
+    let result = f();
+    let left = WAVELENGTHS.with(|current| current.replace(previous));
+    (result, left.unwrap_or(wavelengths))
+}
+
+// Result of f evaluated in RGB, for colors that are upsampled as a whole
+pub fn in_rgb<T>(f: impl FnOnce() -> T) -> T {
+    let previous = WAVELENGTHS.with(|current| current.replace(None));
+    let result = f();
+    WAVELENGTHS.with(|current| current.set(previous));
+    result
+}
+
+pub fn terminate_secondary() {
+    WAVELENGTHS.with(|current| {
+        if let Some(mut wavelengths) = current.get() {
+            wavelengths.terminate_secondary();
+            current.set(Some(wavelengths));
+        }
+    });
+}
+
+// CIE 1931 2° color matching functions, multi-lobe fit by Wyman et al. 2013
+pub fn xyz_matching(lambda: Float) -> [Float; 3] {
+    let lobe = |mu: Float, sigma_low: Float, sigma_high: Float| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+// CIE standard illuminant D65 from 360 to 830nm in steps of 10nm
+const D65: [Float; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.,
+    96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146,
+    82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+    63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+// CIE fluorescent illuminants from 380 to 780nm in steps of 5nm: F2 cool white,
+// F7 broadband daylight and F11 narrowband triphosphor
+const F2: [Float; 81] = [
+    1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63,
+    6.93, 7.19, 7.40, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04,
+    8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73,
+    16.54, 15.21, 13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55,
+    2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68, 0.61, 0.56, 0.54, 0.51, 0.47, 0.47,
+    0.43, 0.46, 0.47, 0.40, 0.33, 0.27,
+];
+
+const F7: [Float; 81] = [
+    2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14, 17.52, 11.35, 12.00,
+    12.58, 13.08, 13.45, 13.71, 13.88, 13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93,
+    12.78, 12.60, 12.44, 12.33, 12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75,
+    12.83, 12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95, 10.76, 10.42, 10.11, 10.04,
+    10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08, 2.73,
+    2.47, 2.25, 2.06, 1.90, 1.75, 1.62, 1.54, 1.45, 1.32, 1.17, 0.99, 0.81,
+];
+
+const F11: [Float; 81] = [
+    0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19,
+    7.12, 6.72, 6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.10, 0.89, 0.83,
+    1.18, 4.90, 39.59, 72.84, 32.61, 7.52, 2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33,
+    9.72, 55.27, 42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33,
+    1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57, 0.27, 0.23, 0.21, 0.24, 0.24, 0.20, 0.24,
+    0.32, 0.26, 0.16, 0.12, 0.09,
+];
+
+// Linear interpolation of values every step nm from start, zero outside
+fn tabulated(table: &[Float], start: Float, step: Float, lambda: Float) -> Float {
+    let x = (lambda - start) / step;
+    let last = (table.len() - 1) as Float;
+    if !(0. ..=last).contains(&x) {
+        return 0.;
+    }
+    let i = (x as usize).min(table.len() - 2);
+    let t = x - i as Float;
+    (1. - t) * table[i] + t * table[i + 1]
+}
+
+// Relative spectral power, 1 at 560nm
+pub fn d65(lambda: Float) -> Float {
+    tabulated(&D65, LAMBDA_MIN, 10., lambda) / 100.
+}
+
+// Spectral radiance of a black body in W/(sr m² nm), wavelength in nm
+pub fn planck(lambda: Float, temperature: Float) -> Float {
+    let lambda = lambda * 1e-9;
+    let radiance =
+        1.191042972e-16 / (lambda.powi(5) * ((1.438777e-2 / (lambda * temperature)).exp() - 1.));
+    radiance * 1e-9
+}
+
+// Maximum luminous efficacy, lm/W at 555nm
+const LUMINOUS_EFFICACY: Float = 683.;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Illuminant {
+    // Temperature in Kelvin
+    Blackbody(Float),
+    D65,
+    // Incandescent, a black body at 2856K
+    A,
+    F2,
+    F7,
+    F11,
+}
+
+impl Illuminant {
+    // Relative spectral power distribution
+    pub fn power(self, lambda: Float) -> Float {
+        match self {
+            Illuminant::Blackbody(temperature) => planck(lambda, temperature),
+            Illuminant::D65 => d65(lambda),
+            Illuminant::A => planck(lambda, 2856.),
+            Illuminant::F2 => tabulated(&F2, 380., 5., lambda),
+            Illuminant::F7 => tabulated(&F7, 380., 5., lambda),
+            Illuminant::F11 => tabulated(&F11, 380., 5., lambda),
+        }
+    }
+
+    // Linear sRGB with a luminance of 1, D65 is white
+    pub fn color(self) -> Color {
+        let color = self.unnormalized_color();
+        color / color.luminance()
+    }
+
+    // Of the power as given
+    fn unnormalized_color(self) -> Color {
+        if let Illuminant::Blackbody(temperature) = self {
+            assert!(temperature > 0., "black body at {}K", temperature);
+        }
+        let xyz =
+            [0, 1, 2].map(|c| integrate(|lambda| xyz_matching(lambda)[c] * self.power(lambda)));
+        let rgb = transform(&XYZ_TO_RGB, xyz);
+        let white = conversion().white;
+        // Clamped to the gamut, very low temperatures are out of it
+        Color::new(
+            (rgb[0] / white[0]).max(0.),
+            (rgb[1] / white[1]).max(0.),
+            (rgb[2] / white[2]).max(0.),
+        )
+    }
+
+    // Lumens per watt of radiant flux, black bodies also radiate outside of the
+    // sampled range, mostly as infrared
+    pub fn efficacy(self) -> Float {
+        let luminous = integrate(|lambda| xyz_matching(lambda)[1] * self.power(lambda));
+        let radiant = match self {
+            // Stefan-Boltzmann law for the radiance
+            Illuminant::Blackbody(temperature) => 5.670374e-8 * temperature.powi(4) / PI,
+            Illuminant::A => 5.670374e-8 * Float::powi(2856., 4) / PI,
+            _ => integrate(|lambda| self.power(lambda)),
+        };
+        LUMINOUS_EFFICACY * luminous / radiant
+    }
+}
+
+// Over the sampled range in steps of 1nm
+fn integrate(f: impl Fn(Float) -> Float) -> Float {
+    let mut sum = 0.;
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        sum += f(lambda);
+        lambda += 1.;
+    }
+    sum
+}
+
+// Linear sRGB from XYZ
+const XYZ_TO_RGB: [[Float; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+fn transform(matrix: &[[Float; 3]; 3], v: [Float; 3]) -> [Float; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn smoothstep(low: Float, high: Float, x: Float) -> Float {
+    let t = ((x - low) / (high - low)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+// Smooth blue, green and red bands summing to one
+fn basis(lambda: Float) -> [Float; 3] {
+    let blue = 1. - smoothstep(470., 510., lambda);
+    let red = smoothstep(565., 605., lambda);
+    [red, 1. - blue - red, blue]
+}
+
+struct Conversion {
+    // RGB of D65, white on the film
+    white: [Float; 3],
+    // From RGB to the weights of the basis
+    to_basis: [[Float; 3]; 3],
+}
+
+// Spectra are upsampled as combinations of the basis, chosen so that they are
+// converted back to the same RGB at the film. Reflectances are lit by D65, so
+// white is a constant spectrum of one.
+fn conversion() -> &'static Conversion {
+    static CONVERSION: OnceLock<Conversion> = OnceLock::new();
+    CONVERSION.get_or_init(|| {
+        let mut white = [0.; 3];
+        let mut bands = [[0.; 3]; 3];
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let xyz = xyz_matching(lambda).map(|v| v * d65(lambda));
+            let weights = basis(lambda);
+            for c in 0..3 {
+                white[c] += xyz[c];
+                for (band, weight) in bands.iter_mut().zip(weights) {
+                    band[c] += weight * xyz[c];
+                }
+            }
+            lambda += 1.;
+        }
+
+        let white = transform(&XYZ_TO_RGB, white);
+        // Columns are the RGB of each band
+        let mut matrix = [[0.; 3]; 3];
+        for (j, band) in bands.iter().enumerate() {
+            let rgb = transform(&XYZ_TO_RGB, *band);
+            for i in 0..3 {
+                matrix[i][j] = rgb[i] / white[i];
+            }
+        }
+        Conversion {
+            white,
+            to_basis: invert(&matrix),
+        }
+    })
+}
+
+fn invert(m: &[[Float; 3]; 3]) -> [[Float; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<Float>();
+    let mut inverse = [[0.; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = cofactor(j, i) / determinant;
+        }
+    }
+    inverse
+}
+
+fn upsample(color: Color, wavelengths: &Wavelengths) -> Color {
+    let weights = transform(&conversion().to_basis, [color.red, color.green, color.blue]);
+    wavelengths.map(|lambda| {
+        let bands = basis(lambda);
+        (weights[0] * bands[0] + weights[1] * bands[1] + weights[2] * bands[2]).max(0.)
+    })
+}
+
+// Values at the current wavelengths of a reflectance or other throughput
+// factor given in RGB, unchanged when rendering in RGB
+pub fn reflectance(color: Color) -> Color {
+    match wavelengths() {
+        Some(wavelengths) => upsample(color, &wavelengths),
+        None => color,
+    }
+}
+
+// Values at the current wavelengths of light given in RGB, white is D65
+pub fn illuminant(color: Color) -> Color {
+    match wavelengths() {
+        Some(wavelengths) => upsample(color, &wavelengths) * wavelengths.map(d65),
+        None => color,
+    }
+}
+
+// Light with the spectral power distribution of an illuminant, its line
+// spectra would be lost by upsampling its color. Other colors tint it.
+#[derive(Copy, Clone, Debug)]
+pub struct Spectrum {
+    illuminant: Illuminant,
+    color: Color,
+    // Of the power to a luminance of 1
+    scale: Float,
+}
+
+impl Spectrum {
+    pub fn new(illuminant: Illuminant) -> Spectrum {
+        let color = illuminant.unnormalized_color();
+        Spectrum {
+            illuminant,
+            color: color / color.luminance(),
+            scale: color.luminance().recip(),
+        }
+    }
+
+    // Values at the current wavelengths of light of the color, the
+    // illuminant's own color gives its power
+    pub fn emission(&self, color: Color) -> Color {
+        let wavelengths = match wavelengths() {
+            Some(wavelengths) => wavelengths,
+            None => return color,
+        };
+        let tint = |value: Float, own: Float| if own > 0. { value / own } else { 0. };
+        let tint = Color::new(
+            tint(color.red, self.color.red),
+            tint(color.green, self.color.green),
+            tint(color.blue, self.color.blue),
+        );
+        upsample(tint, &wavelengths)
+            * wavelengths.map(|lambda| self.scale * self.illuminant.power(lambda))
+    }
+}
+
+// Linear sRGB estimate from radiance at the wavelengths
+pub fn to_rgb(samples: Color, wavelengths: &Wavelengths) -> Color {
+    let values = [samples.red, samples.green, samples.blue];
+    let mut xyz = [0.; 3];
+    for (i, value) in values.iter().enumerate() {
+        if wavelengths.pdf[i] > 0. {
+            let matching = xyz_matching(wavelengths.lambda[i]);
+            for c in 0..3 {
+                xyz[c] += value * matching[c] / (3. * wavelengths.pdf[i]);
+            }
+        }
+    }
+    let rgb = transform(&XYZ_TO_RGB, xyz);
+    let white = conversion().white;
+    Color::new(rgb[0] / white[0], rgb[1] / white[1], rgb[2] / white[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mean over stratified wavelengths of the RGB estimate of f's radiance
+    fn estimate(f: impl Fn() -> Color) -> Color {
+        let n = 1000;
+        let mut sum = Color::BLACK;
+        for i in 0..n {
+            let wavelengths = Wavelengths::sample((i as Float + 0.5) / n as Float);
+            let (radiance, wavelengths) = with_wavelengths(wavelengths, &f);
+            sum += to_rgb(radiance, &wavelengths);
+        }
+        sum / n as Float
+    }
+
+    fn assert_close(a: Color, b: Color) {
+        let error = (a.red - b.red)
+            .abs()
+            .max((a.green - b.green).abs())
+            .max((a.blue - b.blue).abs());
+        assert!(error < 1e-2, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn round_trip() {
+        let colors = [
+            Color::new(1., 1., 1.),
+            Color::new(0.8, 0.2, 0.1),
+            Color::new(0.05, 0.4, 0.9),
+        ];
+        for color in colors {
+            assert_close(estimate(|| illuminant(color)), color);
+            // Reflectances lit by white
+            assert_close(
+                estimate(|| reflectance(color) * illuminant(Color::new(1., 1., 1.))),
+                color,
+            );
+        }
+    }
+
+    #[test]
+    fn rgb_unchanged() {
+        let color = Color::new(0.8, 0.2, 0.1);
+        assert_eq!(illuminant(color), color);
+        let wavelengths = Wavelengths::sample(0.5);
+        let (inner, _) = with_wavelengths(wavelengths, || in_rgb(|| reflectance(color)));
+        assert_eq!(inner, color);
+    }
+
+    #[test]
+    fn illuminant_spectrum_keeps_its_color() {
+        for illuminant in [Illuminant::D65, Illuminant::A, Illuminant::Blackbody(6000.)] {
+            let spectrum = Spectrum::new(illuminant);
+            let color = illuminant.color();
+            assert_close(estimate(|| spectrum.emission(color)), color);
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use crate::buffer::Buffer;
+use crate::color::Color;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::geometries::Hit;
+use crate::noise::Noise;
+use crate::point::Point;
+use crate::vector::Vector;
+
+// Spatially varying material parameter
+pub trait Texture: Send + Sync {
+    fn color(&self, hit: &Hit) -> Color;
+
+    // Scalar parameters use the mean of the channels
+    fn value(&self, hit: &Hit) -> Float {
+        let color = self.color(hit);
+        (color.red + color.green + color.blue) / 3.
+    }
+}
+
+impl Texture for Color {
+    fn color(&self, _hit: &Hit) -> Color {
+        *self
+    }
+}
+
+impl Texture for Float {
+    fn color(&self, _hit: &Hit) -> Color {
+        Color::new(*self, *self, *self)
+    }
+
+    fn value(&self, _hit: &Hit) -> Float {
+        *self
+    }
+}
+
+// Longitude and latitude of a direction, v increases towards +y
+pub fn spherical_uv(direction: Vector) -> (Float, Float) {
+    let d = direction.normalize();
+    (
+        0.5 + d.x.atan2(d.z) / (2. * PI),
+        0.5 + d.y.clamp(-1., 1.).asin() / PI,
+    )
+}
+
+// Partial derivatives of the direction on the unit sphere with respect to
+// spherical_uv, any tangents at the poles
+pub fn spherical_tangents(direction: Vector) -> (Vector, Vector) {
+    let d = direction.normalize();
+    let cos_lat = (d.x * d.x + d.z * d.z).sqrt();
+    if cos_lat == 0. {
+        return d.orthonormals();
+    }
+    (
+        2. * PI * Vector::new(d.z, 0., -d.x),
+        PI * Vector::new(-d.y * d.x / cos_lat, cos_lat, -d.y * d.z / cos_lat),
+    )
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn apply(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    // Blends the two mip levels nearest to the footprint of the hit, bilinear
+    // without one
+    Trilinear,
+    // Blurred to a fixed footprint in UV units, whatever the distance
+    Blurred(Float),
+}
+
+struct MipLevel {
+    width: usize,
+    height: usize,
+    // Row-major, the first row is the top of the image
+    texels: Vec<Color>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i64, y: i64, wrap: Wrap) -> Color {
+        self.texels[wrap.apply(y, self.height) * self.width + wrap.apply(x, self.width)]
+    }
+
+    // Box filtered half resolution version
+    fn downsample(&self) -> MipLevel {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let sum = self.texel(2 * x, 2 * y, Wrap::Clamp)
+                    + self.texel(2 * x + 1, 2 * y, Wrap::Clamp)
+                    + self.texel(2 * x, 2 * y + 1, Wrap::Clamp)
+                    + self.texel(2 * x + 1, 2 * y + 1, Wrap::Clamp);
+                texels.push(0.25 * sum);
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    pub wrap: Wrap,
+    pub filter: Filter,
+}
+
+impl ImageTexture {
+    // Linear texels, row-major starting at the top left
+    pub fn new(
+        width: usize,
+        height: usize,
+        texels: Vec<Color>,
+        wrap: Wrap,
+        filter: Filter,
+    ) -> ImageTexture {
+        assert!(
+            width > 0 && height > 0,
+            "empty {}x{} texture",
+            width,
+            height
+        );
+        assert_eq!(texels.len(), width * height);
+
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(level) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = level.downsample();
+            levels.push(next);
+        }
+
+        ImageTexture {
+            levels,
+            wrap,
+            filter,
+        }
+    }
+
+    fn from_buffer_with(
+        buffer: &Buffer,
+        wrap: Wrap,
+        filter: Filter,
+        decode: fn([u8; 3]) -> Color,
+    ) -> ImageTexture {
+        let (width, height) = (buffer.width(), buffer.height());
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                texels.push(decode(buffer.get(x, y)));
+            }
+        }
+        ImageTexture::new(width as usize, height as usize, texels, wrap, filter)
+    }
+
+    pub fn from_buffer(buffer: &Buffer, wrap: Wrap, filter: Filter) -> ImageTexture {
+        ImageTexture::from_buffer_with(buffer, wrap, filter, Color::from)
+    }
+
+    // For data such as normal and height maps, skips the sRGB decoding
+    pub fn from_buffer_linear(buffer: &Buffer, wrap: Wrap, filter: Filter) -> ImageTexture {
+        ImageTexture::from_buffer_with(buffer, wrap, filter, |rgb| {
+            Color::new(
+                rgb[0] as Float / 255.,
+                rgb[1] as Float / 255.,
+                rgb[2] as Float / 255.,
+            )
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    fn nearest(&self, level: usize, uv: (Float, Float)) -> Color {
+        let level = &self.levels[level];
+        let x = (uv.0 * level.width as Float).floor() as i64;
+        let y = ((1. - uv.1) * level.height as Float).floor() as i64;
+        level.texel(x, y, self.wrap)
+    }
+
+    fn bilinear(&self, level: usize, uv: (Float, Float)) -> Color {
+        let level = &self.levels[level];
+        let x = uv.0 * level.width as Float - 0.5;
+        let y = (1. - uv.1) * level.height as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        (1. - fx) * (1. - fy) * level.texel(x0, y0, self.wrap)
+            + fx * (1. - fy) * level.texel(x0 + 1, y0, self.wrap)
+            + (1. - fx) * fy * level.texel(x0, y0 + 1, self.wrap)
+            + fx * fy * level.texel(x0 + 1, y0 + 1, self.wrap)
+    }
+
+    // Filtered lookup for a footprint of the given width in UV units
+    pub fn lookup(&self, uv: (Float, Float), width: Float) -> Color {
+        let texels = width * self.width().max(self.height()) as Float;
+        let last = (self.levels.len() - 1) as Float;
+        let level = texels.max(1.).log2().min(last);
+
+        let lower = level.floor();
+        let t = level - lower;
+        let lower = lower as usize;
+        if t == 0. {
+            self.bilinear(lower, uv)
+        } else {
+            (1. - t) * self.bilinear(lower, uv) + t * self.bilinear(lower + 1, uv)
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color(&self, hit: &Hit) -> Color {
+        match self.filter {
+            Filter::Nearest => self.nearest(0, hit.uv),
+            Filter::Bilinear => self.bilinear(0, hit.uv),
+            // World units per UV unit from the area spanned by the derivatives
+            Filter::Trilinear => {
+                let scale = hit.dpdu.cross(hit.dpdv).norm().sqrt();
+                let width = if scale > 0. {
+                    hit.footprint / scale
+                } else {
+                    0.
+                };
+                self.lookup(hit.uv, width)
+            }
+            Filter::Blurred(width) => self.lookup(hit.uv, width),
+        }
+    }
+}
+
+// Solid checkerboard in world space, independent of the UV parametrization
+pub struct Checkerboard {
+    pub even: Box<dyn Texture>,
+    pub odd: Box<dyn Texture>,
+    pub size: Float,
+}
+
+impl Texture for Checkerboard {
+    fn color(&self, hit: &Hit) -> Color {
+        let p = hit.point;
+        let cell =
+            (p.x / self.size).floor() + (p.y / self.size).floor() + (p.z / self.size).floor();
+        if cell.rem_euclid(2.) == 0. {
+            self.even.color(hit)
+        } else {
+            self.odd.color(hit)
+        }
+    }
+}
+
+// Grid lines over the UV square, for checking parametrizations
+pub struct UVGrid {
+    pub color: Color,
+    pub line_color: Color,
+    pub cells: u32,
+    // Fraction of a cell
+    pub line_width: Float,
+}
+
+impl Texture for UVGrid {
+    fn color(&self, hit: &Hit) -> Color {
+        let on_line = |t: Float| {
+            let f = (t * self.cells as Float).rem_euclid(1.);
+            f < self.line_width / 2. || f > 1. - self.line_width / 2.
+        };
+        if on_line(hit.uv.0) || on_line(hit.uv.1) {
+            self.line_color
+        } else {
+            // Tint the cells by their coordinates to show the orientation
+            let (u, v) = (hit.uv.0.rem_euclid(1.), hit.uv.1.rem_euclid(1.));
+            self.color * Color::new(0.5 + 0.5 * u, 0.5 + 0.5 * v, 0.5)
+        }
+    }
+}
+
+fn noise_mix(low: &dyn Texture, high: &dyn Texture, t: Float, hit: &Hit) -> Color {
+    let t = t.clamp(0., 1.);
+    (1. - t) * low.color(hit) + t * high.color(hit)
+}
+
+// Blends between two textures using solid noise evaluated at the hit point
+pub struct NoiseTexture {
+    pub noise: Box<dyn Noise>,
+    // Size of the features in world units
+    pub scale: Float,
+    pub low: Box<dyn Texture>,
+    pub high: Box<dyn Texture>,
+}
+
+impl Texture for NoiseTexture {
+    fn color(&self, hit: &Hit) -> Color {
+        let (min, max) = self.noise.range();
+        let point = Point::ORIGIN + hit.point.into_vector() * self.scale.recip();
+        let t = (self.noise.noise(point) - min) / (max - min);
+        noise_mix(self.low.as_ref(), self.high.as_ref(), t, hit)
+    }
+}
+
+// Veins along the x-axis distorted by noise, use Turbulence for classic marble
+pub struct Marble {
+    pub noise: Box<dyn Noise>,
+    pub scale: Float,
+    // Number of veins per unit length
+    pub frequency: Float,
+    pub distortion: Float,
+    pub low: Box<dyn Texture>,
+    pub high: Box<dyn Texture>,
+}
+
+impl Texture for Marble {
+    fn color(&self, hit: &Hit) -> Color {
+        let point = Point::ORIGIN + hit.point.into_vector() * self.scale.recip();
+        let phase =
+            2. * PI * self.frequency * hit.point.x + self.distortion * self.noise.noise(point);
+        noise_mix(
+            self.low.as_ref(),
+            self.high.as_ref(),
+            0.5 + 0.5 * phase.sin(),
+            hit,
+        )
+    }
+}
+
+// Growth rings around the y-axis distorted by noise
+pub struct Wood {
+    pub noise: Box<dyn Noise>,
+    pub scale: Float,
+    // Number of rings per unit length
+    pub frequency: Float,
+    pub distortion: Float,
+    pub low: Box<dyn Texture>,
+    pub high: Box<dyn Texture>,
+}
+
+impl Texture for Wood {
+    fn color(&self, hit: &Hit) -> Color {
+        let point = Point::ORIGIN + hit.point.into_vector() * self.scale.recip();
+        let radius = hit.point.x.hypot(hit.point.z);
+        let rings = self.frequency * radius + self.distortion * self.noise.noise(point);
+        noise_mix(
+            self.low.as_ref(),
+            self.high.as_ref(),
+            rings.rem_euclid(1.),
+            hit,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(uv: (Float, Float), footprint: Float) -> Hit {
+        Hit {
+            point: Point::ORIGIN,
+            normal: Vector::K,
+            uv,
+            dpdu: Vector::new(2., 0., 0.),
+            dpdv: Vector::new(0., 2., 0.),
+            footprint,
+        }
+    }
+
+    #[test]
+    fn trilinear_level_from_footprint() {
+        // Alternating black and white texels
+        let size = 64;
+        let texels = (0..size * size)
+            .map(|i| {
+                let value = ((i / size + i % size) % 2) as Float;
+                Color::new(value, value, value)
+            })
+            .collect();
+        let texture = ImageTexture::new(size, size, texels, Wrap::Repeat, Filter::Trilinear);
+
+        // At a texel center, the surface spans 2 world units per UV unit
+        let uv = (0.5 / size as Float, 1. - 0.5 / size as Float);
+        assert_eq!(texture.color(&hit(uv, 0.)).red, 0.);
+        assert_eq!(texture.color(&hit(uv, 1. / size as Float)).red, 0.);
+        // Two texels wide and more
+        for footprint in [4. / size as Float, 1., 100.] {
+            let color = texture.color(&hit(uv, footprint));
+            assert!((color.red - 0.5).abs() < 1e-6);
+        }
+        // Between the first two levels
+        let color = texture.color(&hit(uv, 3. / size as Float));
+        assert!(color.red > 0. && color.red < 0.5);
+    }
+
+    #[test]
+    fn spherical_derivatives() {
+        let direction = Vector::new(0.3, -0.4, 0.5).normalize();
+        let (dpdu, dpdv) = spherical_tangents(direction);
+        let (u, v) = spherical_uv(direction);
+        let h = 1e-4;
+        for (dp, (du, dv)) in [(dpdu, (h, 0.)), (dpdv, (0., h))] {
+            let moved = spherical_uv(direction + h * dp);
+            assert!((moved.0 - u - du).abs() < 1e-6);
+            assert!((moved.1 - v - dv).abs() < 1e-6);
+        }
+    }
+}
@@ -1,63 +1,65 @@
+use crate::float::{Float, Scalar};
 use std::ops::{Add, Mul, Neg, Sub, Rem};
 
+// Double precision vectors, as used by SDFs, are Vector<f64>
 #[derive(Copy, Clone, Debug)]
-pub struct Vector {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+pub struct Vector<T = Float> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
 }
 
-impl Vector {
-    pub const NULL: Vector = Vector {
-        x: 0.,
-        y: 0.,
-        z: 0.,
+impl<T: Scalar> Vector<T> {
+    pub const NULL: Vector<T> = Vector {
+        x: T::ZERO,
+        y: T::ZERO,
+        z: T::ZERO,
     };
 
-    pub const I: Vector = Vector {
-        x: 1.,
-        y: 0.,
-        z: 0.,
+    pub const I: Vector<T> = Vector {
+        x: T::ONE,
+        y: T::ZERO,
+        z: T::ZERO,
     };
 
-    pub const J: Vector = Vector {
-        x: 0.,
-        y: 1.,
-        z: 0.,
+    pub const J: Vector<T> = Vector {
+        x: T::ZERO,
+        y: T::ONE,
+        z: T::ZERO,
     };
 
-    pub const K: Vector = Vector {
-        x: 0.,
-        y: 0.,
-        z: 1.,
+    pub const K: Vector<T> = Vector {
+        x: T::ZERO,
+        y: T::ZERO,
+        z: T::ONE,
     };
 
-    pub fn new(x: f64, y: f64, z: f64) -> Vector {
+    pub fn new(x: T, y: T, z: T) -> Vector<T> {
         Vector { x, y, z }
     }
 
-    pub fn norm_squared(self) -> f64 {
+    pub fn norm_squared(self) -> T {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn norm(self) -> f64 {
+    pub fn norm(self) -> T {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
-    pub fn normalize(self) -> Vector {
+    pub fn normalize(self) -> Vector<T> {
         let norm = self.norm();
-        if norm != 0.0 {
-            norm.recip() * self
+        if norm != T::ZERO {
+            self * norm.recip()
         } else {
             self
         }
     }
 
-    pub fn dot(self, other: Vector) -> f64 {
+    pub fn dot(self, other: Vector<T>) -> T {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
-    pub fn kron(self, other: Vector) -> Vector {
+    pub fn kron(self, other: Vector<T>) -> Vector<T> {
         Vector {
             x: self.x * other.x,
             y: self.y * other.y,
@@ -65,7 +67,7 @@ impl Vector {
         }
     }
 
-    pub fn cross(self, other: Vector) -> Vector {
+    pub fn cross(self, other: Vector<T>) -> Vector<T> {
         Vector {
             x: self.y * other.z - self.z * other.y,
             y: self.z * other.x - self.x * other.z,
@@ -73,9 +75,9 @@ impl Vector {
         }
     }
 
-    pub fn orthonormals(self) -> (Vector, Vector) {
+    pub fn orthonormals(self) -> (Vector<T>, Vector<T>) {
         let n = self.normalize();
-        if (n.x - n.y).abs() > 0.25 && (n.x - n.z).abs() > 0.25 {
+        if (n.x - n.y).abs() > T::from_f64(0.25) && (n.x - n.z).abs() > T::from_f64(0.25) {
             let t = Vector {
                 x: n.z - n.y,
                 y: n.x - n.z,
@@ -94,7 +96,7 @@ impl Vector {
         }
     }
 
-    pub fn map(self, f: fn(f64) -> f64) -> Vector {
+    pub fn map(self, f: fn(T) -> T) -> Vector<T> {
         Vector {
             x: f(self.x),
             y: f(self.y),
@@ -102,7 +104,7 @@ impl Vector {
         }
     }
 
-    pub fn abs(self) -> Vector {
+    pub fn abs(self) -> Vector<T> {
         Vector {
             x: self.x.abs(),
             y: self.y.abs(),
@@ -110,7 +112,7 @@ impl Vector {
         }
     }
 
-    pub fn max(self, other: f64) -> Vector {
+    pub fn max(self, other: T) -> Vector<T> {
         Vector {
             x: self.x.max(other),
             y: self.y.max(other),
@@ -118,11 +120,11 @@ impl Vector {
         }
     }
 
-    pub fn maxcomp(self) -> f64 {
+    pub fn maxcomp(self) -> T {
         self.x.max(self.y).max(self.z)
     }
 
-    pub fn rem_euclid(self, other: f64) -> Vector {
+    pub fn rem_euclid(self, other: T) -> Vector<T> {
         Vector {
             x: self.x.rem_euclid(other),
             y: self.y.rem_euclid(other),
@@ -130,7 +132,16 @@ impl Vector {
         }
     }
 
-    pub fn rotate_about_x_axis(self, angle: f64) -> Vector {
+    // In the other precision
+    pub fn cast<U: Scalar>(self) -> Vector<U> {
+        Vector {
+            x: U::from_f64(self.x.to_f64()),
+            y: U::from_f64(self.y.to_f64()),
+            z: U::from_f64(self.z.to_f64()),
+        }
+    }
+
+    pub fn rotate_about_x_axis(self, angle: T) -> Vector<T> {
         let (s, c) = angle.sin_cos();
         Vector {
             x: self.x,
@@ -139,7 +150,7 @@ impl Vector {
         }
     }
 
-    pub fn rotate_about_y_axis(self, angle: f64) -> Vector {
+    pub fn rotate_about_y_axis(self, angle: T) -> Vector<T> {
         let (s, c) = angle.sin_cos();
         Vector {
             x: c * self.x + s * self.z,
@@ -148,7 +159,7 @@ impl Vector {
         }
     }
 
-    pub fn rotate_about_z_axis(self, angle: f64) -> Vector {
+    pub fn rotate_about_z_axis(self, angle: T) -> Vector<T> {
         let (s, c) = angle.sin_cos();
         Vector {
             x: c * self.x - s * self.y,
@@ -158,10 +169,10 @@ impl Vector {
     }
 }
 
-impl Add for Vector {
-    type Output = Vector;
+impl<T: Scalar> Add for Vector<T> {
+    type Output = Vector<T>;
 
-    fn add(self, other: Vector) -> Vector {
+    fn add(self, other: Vector<T>) -> Vector<T> {
         Vector {
             x: self.x + other.x,
             y: self.y + other.y,
@@ -170,30 +181,22 @@ impl Add for Vector {
     }
 }
 
-impl Add<Vector> for f64 {
-    type Output = Vector;
+impl<T: Scalar> Add<T> for Vector<T> {
+    type Output = Vector<T>;
 
-    fn add(self, other: Vector) -> Vector {
+    fn add(self, other: T) -> Vector<T> {
         Vector {
-            x: self + other.x,
-            y: self + other.y,
-            z: self + other.z,
+            x: self.x + other,
+            y: self.y + other,
+            z: self.z + other,
         }
     }
 }
 
-impl Add<f64> for Vector {
-    type Output = Vector;
+impl<T: Scalar> Sub for Vector<T> {
+    type Output = Vector<T>;
 
-    fn add(self, other: f64) -> Vector {
-        other + self
-    }
-}
-
-impl Sub for Vector {
-    type Output = Vector;
-
-    fn sub(self, other: Vector) -> Vector {
+    fn sub(self, other: Vector<T>) -> Vector<T> {
         Vector {
             x: self.x - other.x,
             y: self.y - other.y,
@@ -202,22 +205,10 @@ impl Sub for Vector {
     }
 }
 
-impl Sub<Vector> for f64 {
-    type Output = Vector;
+impl<T: Scalar> Sub<T> for Vector<T> {
+    type Output = Vector<T>;
 
-    fn sub(self, other: Vector) -> Vector {
-        Vector {
-            x: self - other.x,
-            y: self - other.y,
-            z: self - other.z,
-        }
-    }
-}
-
-impl Sub<f64> for Vector {
-    type Output = Vector;
-
-    fn sub(self, other: f64) -> Vector {
+    fn sub(self, other: T) -> Vector<T> {
         Vector {
             x: self.x - other,
             y: self.y - other,
@@ -226,18 +217,18 @@ impl Sub<f64> for Vector {
     }
 }
 
-impl Mul for Vector {
-    type Output = f64;
+impl<T: Scalar> Mul for Vector<T> {
+    type Output = T;
 
-    fn mul(self, other: Vector) -> f64 {
+    fn mul(self, other: Vector<T>) -> T {
         self.dot(other)
     }
 }
 
-impl Mul<f64> for Vector {
-    type Output = Vector;
+impl<T: Scalar> Mul<T> for Vector<T> {
+    type Output = Vector<T>;
 
-    fn mul(self, other: f64) -> Vector {
+    fn mul(self, other: T) -> Vector<T> {
         Vector {
             x: self.x * other,
             y: self.y * other,
@@ -246,18 +237,10 @@ impl Mul<f64> for Vector {
     }
 }
 
-impl Mul<Vector> for f64 {
-    type Output = Vector;
-
-    fn mul(self, other: Vector) -> Vector {
-        other * self
-    }
-}
+impl<T: Scalar> Rem<T> for Vector<T> {
+    type Output = Vector<T>;
 
-impl Rem<f64> for Vector {
-    type Output = Vector;
-
-    fn rem(self, other: f64) -> Vector {
+    fn rem(self, other: T) -> Vector<T> {
         Vector {
             x: self.x % other,
             y: self.y % other,
@@ -266,10 +249,10 @@ impl Rem<f64> for Vector {
     }
 }
 
-impl Neg for Vector {
-    type Output = Vector;
+impl<T: Scalar> Neg for Vector<T> {
+    type Output = Vector<T>;
 
-    fn neg(self) -> Vector {
+    fn neg(self) -> Vector<T> {
         Vector {
             x: -self.x,
             y: -self.y,
@@ -277,3 +260,39 @@ impl Neg for Vector {
         }
     }
 }
+
+// Scalars on the left, for both precisions
+macro_rules! scalar_ops {
+    ($t:ty) => {
+        impl Add<Vector<$t>> for $t {
+            type Output = Vector<$t>;
+
+            fn add(self, other: Vector<$t>) -> Vector<$t> {
+                other + self
+            }
+        }
+
+        impl Sub<Vector<$t>> for $t {
+            type Output = Vector<$t>;
+
+            fn sub(self, other: Vector<$t>) -> Vector<$t> {
+                Vector {
+                    x: self - other.x,
+                    y: self - other.y,
+                    z: self - other.z,
+                }
+            }
+        }
+
+        impl Mul<Vector<$t>> for $t {
+            type Output = Vector<$t>;
+
+            fn mul(self, other: Vector<$t>) -> Vector<$t> {
+                other * self
+            }
+        }
+    };
+}
+
+scalar_ops!(f32);
+scalar_ops!(f64);